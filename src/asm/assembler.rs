@@ -1,136 +1,1204 @@
-use std::collections::HashMap;
-use std::iter::Peekable;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
 use std::mem::size_of;
-use std::str::Chars;
-use vmrs::{Op, OpKind, Word};
+use std::path::{Path, PathBuf};
+use vmrs::machine::{MEMORY_SIZE, PROGRAM_CAPACITY};
+use vmrs::object::{DataKind, DataRange, Import, Line, Link, Object, Relocation, Segment};
+use vmrs::{Op, OpKind, VmError, Word};
 
-pub type Bytes = Vec<u8>;
+use crate::ast::{BinaryOp, Expr, ExprKind, Statement, UnaryOp};
+use crate::diagnostic::{Diagnostic, Span};
+use crate::parser;
+use crate::preprocessor::Preprocessor;
 
-pub struct Assembler<'a> {
-    iterator: Peekable<Chars<'a>>,
-    labels: HashMap<String, Word>,
-    byte: Word,
-    row: usize,
-    col: usize,
+pub type Symbols = HashMap<String, Word>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Namespace {
+    Label,
+    Variable,
+    Constant,
+    /* Labels and variables alike, for operands that only carry an address around */
+    Address,
+}
+
+impl Namespace {
+    fn what(self) -> &'static str {
+        match self {
+            Namespace::Label => "label",
+            Namespace::Variable => "variable",
+            Namespace::Constant => "constant",
+            Namespace::Address => "label or variable",
+        }
+    }
+}
+
+/* An operand referring to symbols, patched once all of them are known */
+struct Fixup {
+    offset: usize,
+    namespace: Namespace,
+    expr: Expr,
+}
+
+/* What an operand moves with when its object is linked next to others */
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Target {
+    Segment(Segment),
+    Import(String),
+}
+
+/* A file taking part in the assembly, either given as an input or included */
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub name: String,
+    pub unicode: String,
+    /// Indices into the line table of the instructions assembled from this file.
+    pub lines: Vec<usize>,
+    includes: Vec<Span>,
+}
+
+impl Source {
+    fn is(&self, span: &Span) -> bool {
+        self.name == span.file && self.includes == span.includes
+    }
+}
+
+pub struct Assembler {
+    code: Vec<u8>,
+    labels: Symbols,
+    variables: Symbols,
+    constants: Symbols,
+    /* What each constant moves with, `None` when it cannot be relocated */
+    constant_weights: HashMap<String, Option<HashMap<Target, i64>>>,
+    externs: HashSet<String>,
+    globals: Vec<(String, Span)>,
+    relocatable: bool,
+    preprocessor: Preprocessor,
+    cell: Word,
+    fixups: Vec<Fixup>,
+    lines: Vec<Line>,
+    data_ranges: Vec<DataRange>,
+    sources: Vec<Source>,
+    include_paths: Vec<PathBuf>,
+    including: Vec<(PathBuf, String)>,
+    diagnostics: Vec<Diagnostic>,
     debug: bool,
 }
 
-impl<'a> Assembler<'a> {
-    pub fn new(unicode: &'a str, labels: HashMap<String, Word>, debug: bool) -> Self {
+impl Assembler {
+    pub fn new(debug: bool, include_paths: Vec<PathBuf>) -> Self {
         Self {
-            iterator: unicode.chars().peekable(),
-            labels,
-            byte: 0,
-            row: 1,
-            col: 0,
+            code: Vec::new(),
+            labels: Symbols::new(),
+            variables: Symbols::new(),
+            constants: Symbols::new(),
+            constant_weights: HashMap::new(),
+            externs: HashSet::new(),
+            globals: Vec::new(),
+            relocatable: false,
+            preprocessor: Preprocessor::new(),
+            cell: 0,
+            fixups: Vec::new(),
+            lines: Vec::new(),
+            data_ranges: Vec::new(),
+            sources: Vec::new(),
+            include_paths,
+            including: Vec::new(),
+            diagnostics: Vec::new(),
             debug,
         }
     }
 
-    fn new_line(&mut self) {
-        self.row += 1;
-        self.col = 0;
-        self.iterator.next();
+    pub fn sources(&self) -> &[Source] {
+        &self.sources
     }
 
-    fn skip_space(&mut self) {
-        while self.iterator.peek().is_some_and(is_space) {
-            self.iterator.next();
-            self.col += 1;
+    /// Keeps the relocation, import and export tables `link` needs in the object.
+    pub fn set_relocatable(&mut self, relocatable: bool) {
+        self.relocatable = relocatable;
+    }
+
+    fn symbols(&mut self, namespace: Namespace) -> &mut Symbols {
+        match namespace {
+            Namespace::Label | Namespace::Address => &mut self.labels,
+            Namespace::Variable => &mut self.variables,
+            Namespace::Constant => &mut self.constants,
         }
     }
 
-    fn next_comment(&mut self) {
-        while self.iterator.peek().is_some_and(|c| c != &'\n') {
-            self.col += 1;
-            self.iterator.next();
+    fn define(
+        &mut self,
+        namespace: Namespace,
+        name: String,
+        value: Word,
+        span: Span,
+    ) -> Result<(), Diagnostic> {
+        let external = namespace == Namespace::Label && self.externs.contains(&name);
+        let symbols = self.symbols(namespace);
+        if external || symbols.contains_key(&name) {
+            return Err(Diagnostic::new(
+                format!("duplicate {}: '{}'", namespace.what(), name),
+                span,
+            ));
+        }
+        symbols.insert(name, value);
+        Ok(())
+    }
+
+    /// Constants shadow everything, otherwise names are looked up in `namespace`.
+    /// Constant expressions may refer to labels that are already defined.
+    /// External labels read as zero until `link` adds their address.
+    fn lookup(&self, name: &str, namespace: Namespace) -> Option<Word> {
+        let symbol = match namespace {
+            Namespace::Label | Namespace::Constant => self.labels.get(name),
+            Namespace::Variable => self.variables.get(name),
+            Namespace::Address => self.labels.get(name).or(self.variables.get(name)),
+        };
+        let external = matches!(namespace, Namespace::Label | Namespace::Address)
+            && self.externs.contains(name);
+        self.constants
+            .get(name)
+            .or(symbol)
+            .copied()
+            .or(external.then_some(0))
+    }
+
+    fn evaluate(&self, expr: &Expr, namespace: Namespace) -> Result<Word, Diagnostic> {
+        expr.evaluate(&|name, span| {
+            self.lookup(name, namespace).ok_or_else(|| {
+                Diagnostic::new(
+                    format!("unrecognized {}: '{}'", namespace.what(), name),
+                    span.clone(),
+                )
+            })
+        })
+    }
+
+    /// Evaluates `expr` now if it has no symbols, otherwise patches the word at `offset` later.
+    fn resolve(
+        &mut self,
+        namespace: Namespace,
+        expr: Expr,
+        offset: usize,
+    ) -> Result<Word, Diagnostic> {
+        if expr.symbols().is_empty() {
+            return self.evaluate(&expr, namespace);
         }
+
+        self.fixups.push(Fixup {
+            offset,
+            namespace,
+            expr,
+        });
+        Ok(0)
     }
 
-    fn next_identifier(&mut self) -> String {
-        let mut name = String::new();
-        while self.iterator.peek().is_some_and(|c| c.is_alphabetic()) {
-            self.col += 1;
-            name.push(self.iterator.next().unwrap());
+    /// How many times each target's base is added into the value of `expr`, or `None`
+    /// when a symbol goes through anything but addition and subtraction.
+    fn weights(&self, expr: &Expr, namespace: Namespace) -> Option<HashMap<Target, i64>> {
+        let fixed = |weights: HashMap<Target, i64>| weights.values().all(|&weight| weight == 0);
+
+        match &expr.kind {
+            ExprKind::Number(_) | ExprKind::String(_) => Some(HashMap::new()),
+            ExprKind::Symbol(name) if self.constants.contains_key(name) => {
+                self.constant_weights.get(name)?.clone()
+            }
+            ExprKind::Symbol(name) => {
+                let target = match namespace {
+                    Namespace::Variable => Some(Target::Segment(Segment::Memory)),
+                    Namespace::Address
+                        if !self.labels.contains_key(name) && self.variables.contains_key(name) =>
+                    {
+                        Some(Target::Segment(Segment::Memory))
+                    }
+                    _ if self.externs.contains(name) => Some(Target::Import(name.clone())),
+                    _ => Some(Target::Segment(Segment::Code)),
+                };
+                Some(target.into_iter().map(|target| (target, 1)).collect())
+            }
+            ExprKind::Unary(UnaryOp::Neg, operand) => Some(
+                self.weights(operand, namespace)?
+                    .into_iter()
+                    .map(|(target, weight)| (target, -weight))
+                    .collect(),
+            ),
+            ExprKind::Binary(op @ (BinaryOp::Add | BinaryOp::Sub), lhs, rhs) => {
+                let sign = match op {
+                    BinaryOp::Add => 1,
+                    _ => -1,
+                };
+                let mut weights = self.weights(lhs, namespace)?;
+                for (target, weight) in self.weights(rhs, namespace)? {
+                    *weights.entry(target).or_insert(0) += sign * weight;
+                }
+                Some(weights)
+            }
+            ExprKind::Unary(_, operand) => {
+                fixed(self.weights(operand, namespace)?).then(HashMap::new)
+            }
+            ExprKind::Binary(_, lhs, rhs) => (fixed(self.weights(lhs, namespace)?)
+                && fixed(self.weights(rhs, namespace)?))
+            .then(HashMap::new),
         }
-        name
     }
 
-    fn next_word(&mut self) -> Result<Word, String> {
-        let mut num = String::new();
-        while self
-            .iterator
-            .peek()
-            .is_some_and(|c| c.is_digit(10) || c == &'.')
-        {
-            self.col += 1;
-            num.push(self.iterator.next().unwrap());
+    fn relocation(&self, expr: &Expr, namespace: Namespace) -> Result<Option<Target>, Diagnostic> {
+        let error = || {
+            Diagnostic::new(
+                "only a single address plus or minus a constant can be relocated".to_string(),
+                expr.span.clone(),
+            )
+        };
+
+        let weights = self.weights(expr, namespace).ok_or_else(error)?;
+        let mut moving = weights.into_iter().filter(|&(_, weight)| weight != 0);
+        match (moving.next(), moving.next()) {
+            (None, _) => Ok(None),
+            (Some((target, 1)), None) => Ok(Some(target)),
+            _ => Err(error()),
         }
+    }
+
+    fn patch(&mut self, fixup: Fixup, link: &mut Link) -> Result<(), Diagnostic> {
+        let value = self.evaluate(&fixup.expr, fixup.namespace)?;
+        self.code[fixup.offset..fixup.offset + size_of::<Word>()]
+            .copy_from_slice(&value.to_be_bytes());
 
-        Ok(num
-            .parse()
-            .map_err(|_| "could not parse number".to_string())?)
+        let target = match self.relocation(&fixup.expr, fixup.namespace) {
+            Err(_) if !self.relocatable => None,
+            result => result?,
+        };
+        match target {
+            Some(Target::Import(name)) if !self.relocatable => Err(Diagnostic::new(
+                format!(
+                    "unresolved external label: '{}', assemble with '-c' and link",
+                    name
+                ),
+                fixup.expr.span,
+            )),
+            Some(Target::Import(name)) => {
+                link.imports.push(Import {
+                    offset: fixup.offset,
+                    name,
+                });
+                Ok(())
+            }
+            Some(Target::Segment(segment)) => {
+                link.relocations.push(Relocation {
+                    offset: fixup.offset,
+                    segment,
+                });
+                Ok(())
+            }
+            None => Ok(()),
+        }
     }
 
-    fn next_label(&mut self) -> Result<(), String> {
-        self.iterator.next().unwrap(); // going over '@'
-        self.next_identifier();
-        self.byte += size_of::<Word>() as i16;
-        Ok(())
+    fn next_name(
+        arguments: &mut impl Iterator<Item = Expr>,
+        directive: &str,
+        what: &str,
+        span: Span,
+    ) -> Result<(String, Span), Diagnostic> {
+        match arguments.next() {
+            Some(Expr {
+                kind: ExprKind::Symbol(name),
+                span,
+            }) => Ok((name, span)),
+            argument => Err(Diagnostic::new(
+                format!("expected a {} name after '.{}'", what, directive),
+                argument.map_or(span, |argument| argument.span),
+            )),
+        }
     }
 
-    fn assemble_op(&mut self) -> Result<Op, String> {
-        let (srow, scol) = (self.row, self.col);
-        let kind: OpKind = self.next_identifier().to_uppercase().try_into()?;
+    fn assemble_directive(
+        &mut self,
+        directive: String,
+        arguments: Vec<Expr>,
+        span: Span,
+    ) -> Result<(), Diagnostic> {
+        let mut arguments = arguments.into_iter();
+
+        match directive.as_str() {
+            "var" => {
+                let (name, span) = Self::next_name(&mut arguments, &directive, "variable", span)?;
+                let size = match arguments.next() {
+                    Some(expr) => match self.evaluate(&expr, Namespace::Constant)? {
+                        size if size < 0 => {
+                            return Err(Diagnostic::new(
+                                format!("variable size cannot be negative: {}", size),
+                                expr.span,
+                            ))
+                        }
+                        size => size,
+                    },
+                    None => 1,
+                };
+                let end = self.cell as usize + size as usize;
+                if end > MEMORY_SIZE {
+                    return Err(Diagnostic::new(
+                        format!("variables exceed the {} memory cells", MEMORY_SIZE),
+                        span,
+                    ));
+                }
+                self.define(Namespace::Variable, name, self.cell, span)?;
+                self.cell = end as Word;
+            }
+            "equ" | "const" => {
+                let (name, name_span) =
+                    Self::next_name(&mut arguments, &directive, "constant", span)?;
+                let Some(expr) = arguments.next() else {
+                    return Err(Diagnostic::new(
+                        format!("expected a value after '{}'", name),
+                        name_span,
+                    ));
+                };
+                let value = self.evaluate(&expr, Namespace::Constant)?;
+                self.define(Namespace::Constant, name.clone(), value, name_span)?;
+                let weights = self.weights(&expr, Namespace::Constant);
+                self.constant_weights.insert(name, weights);
+            }
+            "global" | "extern" => loop {
+                let (name, span) =
+                    Self::next_name(&mut arguments, &directive, "label", span.clone())?;
+                match directive.as_str() {
+                    "global" => self.globals.push((name, span)),
+                    _ if self.labels.contains_key(&name) => {
+                        return Err(Diagnostic::new(
+                            format!("duplicate label: '{}'", name),
+                            span,
+                        ))
+                    }
+                    _ => {
+                        self.externs.insert(name);
+                    }
+                }
+                if arguments.len() == 0 {
+                    break;
+                }
+            },
+            "word" | "byte" | "ascii" | "asciiz" if arguments.len() == 0 => {
+                return Err(Diagnostic::new(
+                    format!("expected a value after '.{}'", directive),
+                    span,
+                ))
+            }
+            "word" => {
+                for expr in arguments.by_ref() {
+                    let value = self.resolve(Namespace::Address, expr, self.code.len())?;
+                    self.code.extend(value.to_be_bytes());
+                }
+            }
+            "byte" => {
+                for expr in arguments.by_ref() {
+                    let value = self.evaluate(&expr, Namespace::Constant)?;
+                    if !(-128..=255).contains(&value) {
+                        return Err(Diagnostic::new(
+                            format!("byte {} out of range (expected -128..=255)", value),
+                            expr.span,
+                        ));
+                    }
+                    self.code.push(value as u8);
+                }
+            }
+            "ascii" | "asciiz" => {
+                for expr in arguments.by_ref() {
+                    let ExprKind::String(string) = expr.kind else {
+                        return Err(Diagnostic::new(
+                            format!("expected a string after '.{}'", directive),
+                            expr.span,
+                        ));
+                    };
+                    self.code.extend(string.bytes());
+                    if directive == "asciiz" {
+                        self.code.push(0);
+                    }
+                }
+            }
+            "zero" => {
+                let size = match arguments.next() {
+                    Some(expr) => self.evaluate(&expr, Namespace::Constant)?,
+                    None => -1,
+                };
+                let size = usize::try_from(size).map_err(|_| {
+                    Diagnostic::new(
+                        "expected a non-negative size after '.zero'".to_string(),
+                        span.clone(),
+                    )
+                })?;
+                self.code.resize(self.code.len() + size, 0);
+            }
+            _ => {
+                return Err(Diagnostic::new(
+                    format!("unknown directive: '.{}'", directive),
+                    span,
+                ))
+            }
+        }
+
+        if let Some(argument) = arguments.next() {
+            return Err(Diagnostic::new(
+                format!("unexpected argument to '.{}'", directive),
+                argument.span,
+            ));
+        }
+        Ok(())
+    }
 
-        self.skip_space();
+    fn assemble_op(
+        &mut self,
+        mnemonic: String,
+        operands: Vec<Expr>,
+        span: Span,
+    ) -> Result<Op, Diagnostic> {
+        let kind: OpKind = mnemonic
+            .to_uppercase()
+            .try_into()
+            .map_err(|error: VmError| Diagnostic::new(error.to_string(), span.clone()))?;
 
+        let mut operands = operands.into_iter();
         let mut operand = None;
 
-        if kind == OpKind::Goto || kind == OpKind::Goif {
-            self.skip_space();
-            let label = self.next_identifier();
-            match self.labels.get(&label) {
-                None => return Err(format!("unrecognized label: '{}'", label)),
-                Some(&address) => operand = Some(address),
-            }
-            self.byte += size_of::<Word>() as i16;
-        } else if kind.has_operand() {
-            operand = Some(self.next_word()?);
-            self.byte += size_of::<Word>() as i16;
+        if kind.has_operand() {
+            let expr = match operands.next() {
+                Some(expr) => expr,
+                None => {
+                    return Err(Diagnostic::new(
+                        format!("expected an operand after '{}'", mnemonic),
+                        span,
+                    ))
+                }
+            };
+
+            let namespace = match kind {
+                _ if kind.has_cell() => Namespace::Variable,
+                OpKind::Push => Namespace::Address,
+                _ => Namespace::Label,
+            };
+            operand = Some(self.resolve(namespace, expr, self.code.len() + 1)?);
+        }
+        if let Some(expr) = operands.next() {
+            return Err(Diagnostic::new(
+                format!("unexpected operand to '{}'", mnemonic),
+                expr.span,
+            ));
         }
-        self.byte += 1;
 
         let op = Op(kind, operand);
-
-        self.skip_space();
         if self.debug {
             println!(
-            "[DEBUG] (byte: {:0>3} | row: {:0>3}, col: {:0>3}) - (row: {:0>3}, col: {:0>3}) | {:?}",
-            self.byte, srow, scol, self.row, self.col, op
-        );
+                "[DEBUG] (byte: {:0>3} | row: {:0>3}, col: {:0>3}) | {:?}",
+                self.code.len(),
+                span.row,
+                span.col,
+                op
+            );
         }
-
         Ok(op)
     }
 
-    pub fn assemble(&mut self) -> Result<Bytes, String> {
-        let mut bytes = Vec::new();
+    fn assemble_statement(&mut self, statement: Statement) -> Result<(), Diagnostic> {
+        match statement {
+            Statement::Label { name, span } => {
+                let address = self.code.len() as Word;
+                self.define(Namespace::Label, name, address, span)
+            }
+            Statement::Directive {
+                name,
+                arguments,
+                span,
+            } => {
+                let offset = self.code.len();
+                let kind = match name.as_str() {
+                    "word" => DataKind::Word,
+                    _ => DataKind::Byte,
+                };
+                self.assemble_directive(name, arguments, span.clone())?;
+                /* Data directives get a line of their own, so the listing shows their bytes */
+                if self.code.len() > offset {
+                    self.add_line(offset, &span);
+                    self.data_ranges.push(DataRange {
+                        offset,
+                        length: self.code.len() - offset,
+                        kind,
+                    });
+                }
+                self.check_capacity(offset, span)
+            }
+            Statement::Instruction {
+                mnemonic,
+                operands,
+                span,
+            } => {
+                let offset = self.code.len();
+                let op = self.assemble_op(mnemonic, operands, span.clone())?;
+                self.add_line(offset, &span);
+                self.code.append(&mut op.into());
+                self.check_capacity(offset, span)
+            }
+        }
+    }
+
+    /// Reports the statement that started at `offset` if it pushed the code past capacity.
+    fn check_capacity(&self, offset: usize, span: Span) -> Result<(), Diagnostic> {
+        match offset <= PROGRAM_CAPACITY && self.code.len() > PROGRAM_CAPACITY {
+            true => Err(Diagnostic::new(
+                format!("code exceeds the {} program bytes", PROGRAM_CAPACITY),
+                span,
+            )),
+            false => Ok(()),
+        }
+    }
+
+    fn add_line(&mut self, offset: usize, span: &Span) {
+        if let Some(source) = self.sources.iter_mut().rev().find(|source| source.is(span)) {
+            source.lines.push(self.lines.len());
+        }
+        self.lines.push(Line {
+            offset,
+            row: span.row,
+            col: span.col,
+        });
+    }
+
+    /// Searches next to the including file first, then the include paths in order.
+    fn find_include(&self, path: &str, from: &str) -> Option<PathBuf> {
+        let local = Path::new(from).parent().map(|dir| dir.join(path));
+        local
+            .into_iter()
+            .chain(self.include_paths.iter().map(|dir| dir.join(path)))
+            .find(|candidate| candidate.is_file())
+    }
+
+    fn include(
+        &mut self,
+        arguments: Vec<Expr>,
+        span: Span,
+        statements: &mut Vec<Statement>,
+    ) -> Result<(), Diagnostic> {
+        let path = match &arguments[..] {
+            [Expr {
+                kind: ExprKind::String(path),
+                ..
+            }] => path,
+            _ => {
+                return Err(Diagnostic::new(
+                    "expected a single path after '.include'".to_string(),
+                    span,
+                ))
+            }
+        };
+
+        let resolved = self.find_include(path, &span.file).ok_or(Diagnostic::new(
+            format!("could not find '{}' in the include paths", path),
+            span.clone(),
+        ))?;
+        let canonical = resolved.canonicalize().unwrap_or(resolved.clone());
+        let name = resolved.to_string_lossy().into_owned();
+
+        if let Some(start) = self
+            .including
+            .iter()
+            .position(|(including, _)| *including == canonical)
+        {
+            let mut cycle: Vec<&str> = self.including[start..]
+                .iter()
+                .map(|(_, name)| name.as_str())
+                .collect();
+            cycle.push(&name);
+            return Err(Diagnostic::new(
+                format!("include cycle: {}", cycle.join(" -> ")),
+                span,
+            ));
+        }
+
+        let unicode = fs::read_to_string(&resolved).map_err(|error| {
+            Diagnostic::new(
+                format!("could not read '{}': {}", name, error),
+                span.clone(),
+            )
+        })?;
+
+        let mut chain = vec![Span {
+            includes: Vec::new(),
+            ..span.clone()
+        }];
+        chain.extend(span.includes);
+        self.load(name, unicode, canonical, chain, statements);
+        Ok(())
+    }
+
+    /// Parses a file into `statements`, splicing in its includes recursively.
+    fn load(
+        &mut self,
+        name: String,
+        unicode: String,
+        canonical: PathBuf,
+        includes: Vec<Span>,
+        statements: &mut Vec<Statement>,
+    ) {
+        let (mut parsed, mut diagnostics) = parser::parse(&name, &unicode);
+        for statement in &mut parsed {
+            statement.set_includes(&includes);
+        }
+        for diagnostic in &mut diagnostics {
+            diagnostic.span.includes = includes.clone();
+        }
+        self.diagnostics.append(&mut diagnostics);
+
+        /* Every inclusion is a source of its own, told apart by its include chain */
+        self.sources.push(Source {
+            name: name.clone(),
+            unicode,
+            lines: Vec::new(),
+            includes,
+        });
+
+        self.including.push((canonical, name));
+        for statement in parsed {
+            match statement {
+                Statement::Directive {
+                    name,
+                    arguments,
+                    span,
+                } if name == "include" => {
+                    if let Err(diagnostic) = self.include(arguments, span, statements) {
+                        self.diagnostics.push(diagnostic);
+                    }
+                }
+                statement => statements.push(statement),
+            }
+        }
+        self.including.pop();
+    }
+
+    pub fn assemble(&mut self, file: &str, unicode: &str) {
+        let canonical = Path::new(file)
+            .canonicalize()
+            .unwrap_or_else(|_| PathBuf::from(file));
+        let mut statements = Vec::new();
+        self.load(
+            file.to_string(),
+            unicode.to_string(),
+            canonical,
+            Vec::new(),
+            &mut statements,
+        );
+        let statements = self.preprocessor.expand(statements, &mut self.diagnostics);
+
+        for statement in statements {
+            if let Err(diagnostic) = self.assemble_statement(statement) {
+                self.diagnostics.push(diagnostic);
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> Result<Object, Vec<Diagnostic>> {
+        let mut link = Link {
+            cells: self.cell,
+            ..Link::default()
+        };
+        for fixup in std::mem::take(&mut self.fixups) {
+            if let Err(diagnostic) = self.patch(fixup, &mut link) {
+                self.diagnostics.push(diagnostic);
+            }
+        }
 
-        while let Some(c) = self.iterator.peek() {
-            match c {
-                c if is_space(c) => self.skip_space(),
-                '|' => self.next_comment(),
-                '\n' => self.new_line(),
-                '@' => self.next_label()?,
-                _ => bytes.append(&mut self.assemble_op()?.into()),
+        for (name, span) in std::mem::take(&mut self.globals) {
+            match self.labels.get(&name) {
+                Some(&address) => {
+                    link.exports.insert(name, address);
+                }
+                None => self.diagnostics.push(Diagnostic::new(
+                    format!("unrecognized label: '{}'", name),
+                    span,
+                )),
             }
         }
 
-        return Ok(bytes);
+        if !self.diagnostics.is_empty() {
+            let sources = self.sources;
+            self.diagnostics.sort_by_key(|diagnostic| {
+                let file = sources
+                    .iter()
+                    .position(|source| source.is(&diagnostic.span));
+                (file, diagnostic.span.row, diagnostic.span.col)
+            });
+            return Err(self.diagnostics);
+        }
+
+        let mut object = Object::new(self.code);
+        object.symbols = Some(self.labels.into_iter().collect::<BTreeMap<_, _>>());
+        object.lines = Some(self.lines);
+        object.data_ranges = self.data_ranges;
+        if self.relocatable {
+            object.link = Some(link);
+        }
+        Ok(object)
     }
 }
 
-fn is_space(c: &char) -> bool {
-    c == &' ' || c == &'\t'
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vmrs::disassembler;
+
+    fn assemble(source: &str) -> Result<Object, Vec<Diagnostic>> {
+        let mut assembler = Assembler::new(false, Vec::new());
+        assembler.assemble("test.asm", source);
+        assembler.finish()
+    }
+
+    #[test]
+    fn test_forward_reference() {
+        let object = assemble("goto end\npush 1\n@end\nhalt\n").unwrap();
+        assert_eq!(
+            object.code,
+            vec![
+                OpKind::Goto.into(),
+                0x00,
+                0x06,
+                OpKind::Push.into(),
+                0x00,
+                0x01,
+                OpKind::Halt.into(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_labels_do_not_shift_offsets() {
+        let object = assemble("@a\n@b\npush 1\n@c\ngoto c\n").unwrap();
+        let symbols = object.symbols.unwrap();
+        assert_eq!(symbols["a"], 0);
+        assert_eq!(symbols["b"], 0);
+        assert_eq!(symbols["c"], 3);
+        assert_eq!(&object.code[3..], &[OpKind::Goto.into(), 0x00, 0x03]);
+    }
+
+    #[test]
+    fn test_negative_operands() {
+        let object = assemble("@loop_1\npush -2\ngoto loop_1\n").unwrap();
+        assert_eq!(
+            object.code,
+            vec![
+                OpKind::Push.into(),
+                0xff,
+                0xfe,
+                OpKind::Goto.into(),
+                0x00,
+                0x00
+            ]
+        );
+    }
+
+    #[test]
+    fn test_literals() {
+        let object = assemble("push 0xff\npush -0b1\npush 'A'\npush -32_768\n").unwrap();
+        assert_eq!(
+            object.code,
+            vec![
+                OpKind::Push.into(),
+                0x00,
+                0xff,
+                OpKind::Push.into(),
+                0xff,
+                0xff,
+                OpKind::Push.into(),
+                0x00,
+                0x41,
+                OpKind::Push.into(),
+                0x80,
+                0x00,
+            ]
+        );
+
+        let diagnostics = assemble("push 32768\n").unwrap_err();
+        assert_eq!(
+            diagnostics[0].message,
+            "literal 32768 does not fit in a word (expected -32768..=32767)"
+        );
+    }
+
+    #[test]
+    fn test_constants_and_expressions() {
+        let source = "\
+            .equ SIZE 4\n\
+            .const MASK ~(SIZE - 1)\n\
+            .var table SIZE * 2\n\
+            push SIZE * 2 + 1\n\
+            push MASK\n\
+            goto end + 3\n\
+            load table + 1\n\
+            @end\n";
+        let object = assemble(source).unwrap();
+        assert_eq!(
+            object.code,
+            vec![
+                OpKind::Push.into(),
+                0x00,
+                0x09,
+                OpKind::Push.into(),
+                0xff,
+                0xfc,
+                OpKind::Goto.into(),
+                0x00,
+                0x0f,
+                OpKind::Load.into(),
+                0x00,
+                0x01,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expression_errors() {
+        let diagnostics =
+            assemble(".equ A B\n.equ C 1\n.equ C 2\npush C + 32767\ngoto x + 1\n").unwrap_err();
+        let messages: Vec<&str> = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.message.as_str())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "unrecognized constant: 'B'",
+                "duplicate constant: 'C'",
+                "arithmetic overflow in expression",
+                "unrecognized label: 'x'",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_includes() {
+        let dir = std::env::temp_dir().join(format!("vmrs-include-{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("lib/std.asm"), ".macro inc\npush 1\nadd\n.endm\n").unwrap();
+        fs::write(dir.join("lib/a.asm"), ".include \"b.asm\"\n").unwrap();
+        fs::write(dir.join("lib/b.asm"), ".include \"a.asm\"\n").unwrap();
+        let main = dir.join("main.asm").to_string_lossy().into_owned();
+
+        let mut assembler = Assembler::new(false, vec![dir.join("lib")]);
+        assembler.assemble(&main, ".include \"std.asm\"\npush 1\ninc\n");
+        let object = assembler.finish().unwrap();
+        assert_eq!(
+            object.code,
+            vec![
+                OpKind::Push.into(),
+                0x00,
+                0x01,
+                OpKind::Push.into(),
+                0x00,
+                0x01,
+                OpKind::Add.into(),
+            ]
+        );
+
+        let mut assembler = Assembler::new(false, vec![dir.join("lib")]);
+        assembler.assemble(&main, ".include \"a.asm\"\n.include \"none.asm\"\n");
+        let diagnostics = assembler.finish().unwrap_err();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let (a, b) = (dir.join("lib/a.asm"), dir.join("lib/b.asm"));
+        assert_eq!(
+            diagnostics[0].message,
+            "could not find 'none.asm' in the include paths"
+        );
+        assert_eq!(
+            diagnostics[1].message,
+            format!(
+                "include cycle: {} -> {} -> {}",
+                a.display(),
+                b.display(),
+                a.display()
+            )
+        );
+        assert_eq!(diagnostics[1].span.includes.len(), 2);
+    }
+
+    #[test]
+    fn test_include_twice() {
+        let dir = std::env::temp_dir().join(format!("vmrs-include-twice-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("x.asm"), "push 7\n").unwrap();
+        fs::write(dir.join("inc.asm"), ".include \"x.asm\"\n").unwrap();
+        fs::write(dir.join("label.asm"), "@twice\n").unwrap();
+        let main = dir.join("main.asm").to_string_lossy().into_owned();
+
+        let mut assembler = Assembler::new(false, Vec::new());
+        assembler.assemble(&main, ".include \"inc.asm\"\npop\n.include \"x.asm\"\n");
+        let sources: Vec<(String, Vec<usize>)> = assembler
+            .sources()
+            .iter()
+            .map(|source| (source.name.clone(), source.lines.clone()))
+            .collect();
+        assembler.finish().unwrap();
+
+        let (inc, x) = (dir.join("inc.asm"), dir.join("x.asm"));
+        assert_eq!(
+            sources,
+            vec![
+                (main.clone(), vec![1]),
+                (inc.to_string_lossy().into_owned(), vec![]),
+                (x.to_string_lossy().into_owned(), vec![0]),
+                (x.to_string_lossy().into_owned(), vec![2]),
+            ]
+        );
+
+        let mut assembler = Assembler::new(false, Vec::new());
+        assembler.assemble(&main, ".include \"label.asm\"\n.include \"label.asm\"\n");
+        let diagnostics = assembler.finish().unwrap_err();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(diagnostics[0].message, "duplicate label: 'twice'");
+        assert_eq!(diagnostics[0].span.includes.len(), 1);
+        assert_eq!(diagnostics[0].span.includes[0].row, 2);
+    }
+
+    #[test]
+    fn test_relocations() {
+        let mut assembler = Assembler::new(false, Vec::new());
+        assembler.set_relocatable(true);
+        assembler.assemble(
+            "test.asm",
+            ".global main\n.extern print\n.var x\n@main\ncall print\ngoto main + 3\nload x\npush end - main\n@end\n",
+        );
+        let link = assembler.finish().unwrap().link.unwrap();
+        assert_eq!(link.cells, 1);
+        assert_eq!(
+            link.relocations,
+            vec![
+                Relocation {
+                    offset: 4,
+                    segment: Segment::Code,
+                },
+                Relocation {
+                    offset: 7,
+                    segment: Segment::Memory,
+                },
+            ]
+        );
+        assert_eq!(
+            link.imports,
+            vec![Import {
+                offset: 1,
+                name: "print".to_string(),
+            }]
+        );
+        assert_eq!(link.exports, BTreeMap::from([("main".to_string(), 0)]));
+
+        /* Constants move with the labels they were derived from */
+        let mut assembler = Assembler::new(false, Vec::new());
+        assembler.set_relocatable(true);
+        assembler.assemble(
+            "test.asm",
+            "@back\ngoto ENTRY\npush SIZE\n@end\n.equ ENTRY back\n.equ SIZE end - back\n",
+        );
+        let link = assembler.finish().unwrap().link.unwrap();
+        assert_eq!(
+            link.relocations,
+            vec![Relocation {
+                offset: 1,
+                segment: Segment::Code,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_relocation_errors() {
+        let diagnostics = assemble(".extern print\ncall print\n").unwrap_err();
+        assert_eq!(
+            diagnostics[0].message,
+            "unresolved external label: 'print', assemble with '-c' and link"
+        );
+
+        let mut assembler = Assembler::new(false, Vec::new());
+        assembler.set_relocatable(true);
+        assembler.assemble("test.asm", ".global nowhere\n@a\npush a * 2\n");
+        let messages: Vec<String> = assembler
+            .finish()
+            .unwrap_err()
+            .into_iter()
+            .map(|diagnostic| diagnostic.message)
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "unrecognized label: 'nowhere'",
+                "only a single address plus or minus a constant can be relocated",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_data() {
+        let source = "\
+            push table\n\
+            loadp\n\
+            halt\n\
+            @table\n\
+            .word end, -2\n\
+            .byte 1, 0xff, -1\n\
+            .ascii \"hi\"\n\
+            .asciiz \"a\", \"\"\n\
+            .zero 2\n\
+            @end\n";
+        let object = assemble(source).unwrap();
+        assert_eq!(
+            object.code,
+            vec![
+                OpKind::Push.into(),
+                0x00,
+                0x05,
+                OpKind::Loadp.into(),
+                OpKind::Halt.into(),
+                0x00,
+                0x13,
+                0xff,
+                0xfe,
+                0x01,
+                0xff,
+                0xff,
+                b'h',
+                b'i',
+                b'a',
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+            ]
+        );
+        assert_eq!(object.symbols.unwrap()["end"], 0x13);
+        assert_eq!(object.lines.unwrap()[4].offset, 9);
+
+        let diagnostics = assemble(".word\n.byte 256\n.ascii 1\n.zero -1\n.zero\n").unwrap_err();
+        let messages: Vec<&str> = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.message.as_str())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "expected a value after '.word'",
+                "byte 256 out of range (expected -128..=255)",
+                "expected a string after '.ascii'",
+                "expected a non-negative size after '.zero'",
+                "expected a non-negative size after '.zero'",
+            ]
+        );
+
+        let diagnostics = assemble(".zero 1020\n.word 1, 2\n.zero 2000\nhalt\n").unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message,
+            "code exceeds the 1024 program bytes"
+        );
+        assert_eq!(diagnostics[0].span.row, 3);
+        assert!(assemble(".zero 1023\nhalt\n").is_ok());
+    }
+
+    #[test]
+    fn test_disassembly_round_trip() {
+        let source = "\
+            push message\n\
+            printp\n\
+            goto end\n\
+            @table\n\
+            .word end, -2\n\
+            @message\n\
+            .asciiz \"hello, world\"\n\
+            .byte 1\n\
+            .zero 3\n\
+            @end\n\
+            halt\n";
+        let object = assemble(source).unwrap();
+        let disassemble = |object: &Object| {
+            disassembler::disassemble(&object.code, &object.data_ranges, object.symbols.as_ref())
+                .unwrap()
+        };
+
+        let output = disassemble(&object);
+        let again = assemble(&output).unwrap();
+        assert_eq!(again.code, object.code);
+        assert_eq!(disassemble(&again), output);
+    }
+
+    #[test]
+    fn test_variables() {
+        let object = assemble("load y\n.var x 4\n.var y\n").unwrap();
+        assert_eq!(object.code, vec![OpKind::Load.into(), 0x00, 0x04]);
+
+        let mut assembler = Assembler::new(false, Vec::new());
+        assembler.set_relocatable(true);
+        assembler.assemble(
+            "test.asm",
+            ".var x\n.var arr 4\npush arr + 2\nloadi\n.word arr\n",
+        );
+        let object = assembler.finish().unwrap();
+        assert_eq!(
+            object.code,
+            vec![
+                OpKind::Push.into(),
+                0x00,
+                0x03,
+                OpKind::Loadi.into(),
+                0x00,
+                0x01
+            ]
+        );
+        assert_eq!(
+            object.link.unwrap().relocations,
+            vec![
+                Relocation {
+                    offset: 1,
+                    segment: Segment::Memory,
+                },
+                Relocation {
+                    offset: 4,
+                    segment: Segment::Memory,
+                },
+            ]
+        );
+
+        let diagnostics = assemble(".var x -3\n.var y 1024\n.var z 1023\n").unwrap_err();
+        let messages: Vec<&str> = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.message.as_str())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "variable size cannot be negative: -3",
+                "variables exceed the 1024 memory cells",
+            ]
+        );
+        assert_eq!(diagnostics[0].span.col, 8);
+
+        let diagnostics = assemble("push nowhere\n").unwrap_err();
+        assert_eq!(
+            diagnostics[0].message,
+            "unrecognized label or variable: 'nowhere'"
+        );
+    }
+
+    #[test]
+    fn test_errors_are_collected() {
+        let diagnostics = assemble("pish 1\ngoto nowhere\npush\n@a\n@a\nhalt 1\n").unwrap_err();
+        let messages: Vec<&str> = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.message.as_str())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "unknown string op kind: 'PISH'",
+                "unrecognized label: 'nowhere'",
+                "expected an operand after 'push'",
+                "duplicate label: 'a'",
+                "unexpected operand to 'halt'",
+            ]
+        );
+    }
 }
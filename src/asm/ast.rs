@@ -0,0 +1,240 @@
+use vmrs::Word;
+
+use crate::diagnostic::{Diagnostic, Span};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    And,
+    Xor,
+    Shl,
+    Shr,
+}
+
+impl BinaryOp {
+    /// Higher binds tighter, mirroring C without `|` which starts a comment.
+    pub fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Xor => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Shl | BinaryOp::Shr => 3,
+            BinaryOp::Add | BinaryOp::Sub => 4,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExprKind {
+    Number(i64),
+    String(String),
+    Symbol(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    /// Every symbol the expression refers to, in source order.
+    pub fn symbols(&self) -> Vec<&str> {
+        match &self.kind {
+            ExprKind::Number(_) | ExprKind::String(_) => Vec::new(),
+            ExprKind::Symbol(name) => vec![name],
+            ExprKind::Unary(_, operand) => operand.symbols(),
+            ExprKind::Binary(_, lhs, rhs) => {
+                let mut symbols = lhs.symbols();
+                symbols.append(&mut rhs.symbols());
+                symbols
+            }
+        }
+    }
+
+    fn set_includes(&mut self, includes: &[Span]) {
+        self.span.includes = includes.to_vec();
+        match &mut self.kind {
+            ExprKind::Number(_) | ExprKind::String(_) | ExprKind::Symbol(_) => {}
+            ExprKind::Unary(_, operand) => operand.set_includes(includes),
+            ExprKind::Binary(_, lhs, rhs) => {
+                lhs.set_includes(includes);
+                rhs.set_includes(includes);
+            }
+        }
+    }
+
+    /// Folds the expression into a single word, failing on any intermediate overflow.
+    pub fn evaluate(
+        &self,
+        lookup: &impl Fn(&str, &Span) -> Result<Word, Diagnostic>,
+    ) -> Result<Word, Diagnostic> {
+        let error = |message: String| Diagnostic::new(message, self.span.clone());
+
+        match &self.kind {
+            ExprKind::Number(value) => literal(*value, &self.span),
+            ExprKind::String(_) => Err(error("expected a number, found a string".to_string())),
+            ExprKind::Symbol(name) => lookup(name, &self.span),
+            ExprKind::Unary(UnaryOp::Neg, operand) => match operand.kind {
+                ExprKind::Number(value) => literal(-value, &self.span),
+                _ => operand
+                    .evaluate(lookup)?
+                    .checked_neg()
+                    .ok_or_else(|| error("arithmetic overflow in expression".to_string())),
+            },
+            ExprKind::Unary(UnaryOp::Not, operand) => Ok(!operand.evaluate(lookup)?),
+            ExprKind::Binary(op, lhs, rhs) => {
+                let (a, b) = (lhs.evaluate(lookup)?, rhs.evaluate(lookup)?);
+                if matches!(op, BinaryOp::Div | BinaryOp::Mod) && b == 0 {
+                    return Err(error("division by zero in expression".to_string()));
+                }
+                if matches!(op, BinaryOp::Shl | BinaryOp::Shr)
+                    && !(0..Word::BITS as Word).contains(&b)
+                {
+                    return Err(error(format!("invalid shift amount: {}", b)));
+                }
+
+                match op {
+                    BinaryOp::Add => a.checked_add(b),
+                    BinaryOp::Sub => a.checked_sub(b),
+                    BinaryOp::Mul => a.checked_mul(b),
+                    BinaryOp::Div => a.checked_div(b),
+                    BinaryOp::Mod => a.checked_rem(b),
+                    BinaryOp::And => Some(a & b),
+                    BinaryOp::Xor => Some(a ^ b),
+                    /* Shifting back must give `a` again, or bits were lost */
+                    BinaryOp::Shl => Some(a << b).filter(|value| value >> b == a),
+                    BinaryOp::Shr => Some(a >> b),
+                }
+                .ok_or_else(|| error("arithmetic overflow in expression".to_string()))
+            }
+        }
+    }
+}
+
+fn literal(value: i64, span: &Span) -> Result<Word, Diagnostic> {
+    Word::try_from(value).map_err(|_| {
+        Diagnostic::new(
+            format!(
+                "literal {} does not fit in a word (expected {}..={})",
+                value,
+                Word::MIN,
+                Word::MAX
+            ),
+            span.clone(),
+        )
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Statement {
+    Label {
+        name: String,
+        span: Span,
+    },
+    Instruction {
+        mnemonic: String,
+        operands: Vec<Expr>,
+        span: Span,
+    },
+    Directive {
+        name: String,
+        arguments: Vec<Expr>,
+        span: Span,
+    },
+}
+
+impl Statement {
+    /// Records the `.include` chain the statement was read through on all of its spans.
+    pub fn set_includes(&mut self, includes: &[Span]) {
+        match self {
+            Statement::Label { span, .. } => span.includes = includes.to_vec(),
+            Statement::Instruction {
+                operands: exprs,
+                span,
+                ..
+            }
+            | Statement::Directive {
+                arguments: exprs,
+                span,
+                ..
+            } => {
+                span.includes = includes.to_vec();
+                for expr in exprs {
+                    expr.set_includes(includes);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn evaluate(source: &str) -> Result<Word, String> {
+        let (statements, diagnostics) = parser::parse("test.asm", &format!("push {}", source));
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        let Statement::Instruction { operands, .. } = &statements[0] else {
+            panic!("expected an instruction");
+        };
+        operands[0]
+            .evaluate(&|name, span| match name {
+                "SIZE" => Ok(8),
+                _ => Err(Diagnostic::new(format!("unknown '{}'", name), span.clone())),
+            })
+            .map_err(|diagnostic| diagnostic.message)
+    }
+
+    #[test]
+    fn test_evaluate() {
+        assert_eq!(evaluate("SIZE * 2 + 1"), Ok(17));
+        assert_eq!(evaluate("(SIZE + 2) * -3"), Ok(-30));
+        assert_eq!(evaluate("1 << 4 ^ 0xff & 0x0f"), Ok(0x1f));
+        assert_eq!(evaluate("~0 - 10 % 4"), Ok(-3));
+        assert_eq!(evaluate("-32768"), Ok(Word::MIN));
+    }
+
+    #[test]
+    fn test_evaluate_errors() {
+        assert_eq!(
+            evaluate("32767 + 1"),
+            Err("arithmetic overflow in expression".to_string())
+        );
+        assert_eq!(
+            evaluate("SIZE / (4 - 4)"),
+            Err("division by zero in expression".to_string())
+        );
+        assert_eq!(
+            evaluate("0x4000 << 2"),
+            Err("arithmetic overflow in expression".to_string())
+        );
+        assert_eq!(
+            evaluate("1 << 15"),
+            Err("arithmetic overflow in expression".to_string())
+        );
+        assert_eq!(evaluate("-1 << 15"), Ok(Word::MIN));
+        assert_eq!(
+            evaluate("1 << 16"),
+            Err("invalid shift amount: 16".to_string())
+        );
+        assert_eq!(evaluate("COUNT"), Err("unknown 'COUNT'".to_string()));
+        assert_eq!(
+            evaluate("1 + \"a\""),
+            Err("expected a number, found a string".to_string())
+        );
+    }
+}
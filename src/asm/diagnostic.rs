@@ -0,0 +1,126 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub file: String,
+    pub row: usize,
+    pub col: usize,
+    pub len: usize,
+    /// The `.include` directives that led to `file`, innermost first.
+    pub includes: Vec<Span>,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.row, self.col)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn new(message: String, span: Span) -> Self {
+        Self { message, span }
+    }
+
+    /// Renders the diagnostic rustc-style, underlining the span in `source`.
+    pub fn render(&self, source: &str) -> String {
+        let line = source.lines().nth(self.span.row - 1).unwrap_or("");
+        let gutter = " ".repeat(self.span.row.to_string().len());
+        let indent: String = line
+            .chars()
+            .take(self.span.col - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        let mut rendered = format!(
+            "error: {}\n{}--> {}\n{} |\n{} | {}\n{} | {}{}",
+            self.message,
+            gutter,
+            self.span,
+            gutter,
+            self.span.row,
+            line,
+            gutter,
+            indent,
+            "^".repeat(self.span.len.max(1))
+        );
+        for include in &self.span.includes {
+            rendered.push_str(&format!("\n{} = note: included from {}", gutter, include));
+        }
+        rendered
+    }
+}
+
+pub fn render_all<'a>(diagnostics: &[Diagnostic], source: impl Fn(&str) -> &'a str) -> String {
+    let mut rendered: Vec<String> = diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.render(source(&diagnostic.span.file)))
+        .collect();
+
+    rendered.push(match diagnostics.len() {
+        1 => "error: could not assemble due to 1 previous error".to_string(),
+        count => format!("error: could not assemble due to {} previous errors", count),
+    });
+    rendered.join("\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let diagnostic = Diagnostic::new(
+            "unrecognized label: 'end'".to_string(),
+            Span {
+                file: "main.asm".to_string(),
+                row: 2,
+                col: 6,
+                len: 3,
+                includes: Vec::new(),
+            },
+        );
+        assert_eq!(
+            diagnostic.render("push 1\ngoto end\n"),
+            "error: unrecognized label: 'end'\n\
+             \x20--> main.asm:2:6\n\
+             \x20 |\n\
+             2 | goto end\n\
+             \x20 |      ^^^"
+        );
+    }
+
+    #[test]
+    fn test_render_includes() {
+        let span = |file: &str, row, includes| Span {
+            file: file.to_string(),
+            row,
+            col: 1,
+            len: 4,
+            includes,
+        };
+        let includes = vec![
+            span("util.asm", 3, Vec::new()),
+            span("main.asm", 1, Vec::new()),
+        ];
+        let diagnostic = Diagnostic::new(
+            "duplicate label: 'exit'".to_string(),
+            span("std.asm", 1, includes),
+        );
+        assert_eq!(
+            diagnostic.render("@exit\n"),
+            "error: duplicate label: 'exit'\n\
+             \x20--> std.asm:1:1\n\
+             \x20 |\n\
+             1 | @exit\n\
+             \x20 | ^^^^\n\
+             \x20 = note: included from util.asm:3:1\n\
+             \x20 = note: included from main.asm:1:1"
+        );
+    }
+}
@@ -0,0 +1,439 @@
+use std::iter::Peekable;
+use std::num::IntErrorKind;
+use std::str::Chars;
+
+use vmrs::Word;
+
+use crate::diagnostic::{Diagnostic, Span};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenKind {
+    Identifier(String),
+    Number(i64),
+    String(String),
+    Label(String),
+    Directive(String),
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Ampersand,
+    Caret,
+    Tilde,
+    ShiftLeft,
+    ShiftRight,
+    LeftParen,
+    RightParen,
+    Comma,
+    Newline,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+pub struct Lexer<'a> {
+    iterator: Peekable<Chars<'a>>,
+    file: &'a str,
+    row: usize,
+    col: usize,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(file: &'a str, unicode: &'a str) -> Self {
+        Self {
+            iterator: unicode.chars().peekable(),
+            file,
+            row: 1,
+            col: 1,
+        }
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.iterator.next()?;
+        if c == '\n' {
+            self.row += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+        Some(c)
+    }
+
+    fn span(&self, row: usize, col: usize) -> Span {
+        Span {
+            file: self.file.to_string(),
+            row,
+            col,
+            len: self.col.saturating_sub(col),
+            includes: Vec::new(),
+        }
+    }
+
+    fn skip_space(&mut self) {
+        while self.iterator.peek().is_some_and(is_space) {
+            self.next_char();
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        while self.iterator.peek().is_some_and(|c| c != &'\n') {
+            self.next_char();
+        }
+    }
+
+    fn next_identifier(&mut self) -> String {
+        let mut name = String::new();
+        while self
+            .iterator
+            .peek()
+            .is_some_and(|c| is_identifier_start(c) || !name.is_empty() && c.is_ascii_digit())
+        {
+            name.push(self.next_char().unwrap());
+        }
+        name
+    }
+
+    fn next_name(&mut self, row: usize, col: usize, what: &str) -> Result<String, Diagnostic> {
+        self.next_char(); // going over the sigil
+        let name = self.next_identifier();
+        if name.is_empty() {
+            return Err(Diagnostic::new(
+                format!("expected a {} name", what),
+                self.span(row, col),
+            ));
+        }
+        Ok(name)
+    }
+
+    fn next_number(&mut self, row: usize, col: usize) -> Result<i64, Diagnostic> {
+        let mut num = String::new();
+        while self
+            .iterator
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == &'_')
+        {
+            num.push(self.next_char().unwrap());
+        }
+
+        let digits: String = num.chars().filter(|c| c != &'_').collect();
+        let (radix, digits) = match digits.get(..2) {
+            Some("0x" | "0X") => (16, &digits[2..]),
+            Some("0b" | "0B") => (2, &digits[2..]),
+            Some("0o" | "0O") => (8, &digits[2..]),
+            _ => (10, &digits[..]),
+        };
+        i64::from_str_radix(digits, radix).map_err(|error| {
+            let message = match error.kind() {
+                IntErrorKind::PosOverflow => format!(
+                    "literal {} does not fit in a word (expected {}..={})",
+                    num,
+                    Word::MIN,
+                    Word::MAX
+                ),
+                _ => format!("could not parse number '{}'", num),
+            };
+            Diagnostic::new(message, self.span(row, col))
+        })
+    }
+
+    fn next_escape(&mut self, row: usize, col: usize) -> Result<char, Diagnostic> {
+        match self.next_char() {
+            Some('n') => Ok('\n'),
+            Some('t') => Ok('\t'),
+            Some('r') => Ok('\r'),
+            Some('0') => Ok('\0'),
+            Some(c @ ('\\' | '\'' | '"')) => Ok(c),
+            _ => Err(Diagnostic::new(
+                "unknown character escape".to_string(),
+                self.span(row, col),
+            )),
+        }
+    }
+
+    fn next_character(&mut self, row: usize, col: usize) -> Result<i64, Diagnostic> {
+        self.next_char(); // going over the opening quote
+        let c = match self.iterator.peek() {
+            Some('\\') => {
+                self.next_char();
+                self.next_escape(row, col)?
+            }
+            Some(&c) if c != '\n' && c != '\'' => {
+                self.next_char();
+                c
+            }
+            _ => {
+                return Err(Diagnostic::new(
+                    "expected a character literal".to_string(),
+                    self.span(row, col),
+                ))
+            }
+        };
+
+        if self.iterator.peek() != Some(&'\'') {
+            return Err(Diagnostic::new(
+                "unterminated character literal".to_string(),
+                self.span(row, col),
+            ));
+        }
+        self.next_char();
+        Ok(c as i64)
+    }
+
+    fn next_string(&mut self, row: usize, col: usize) -> Result<String, Diagnostic> {
+        self.next_char(); // going over the opening quote
+        let mut string = String::new();
+        loop {
+            match self.iterator.peek() {
+                Some('"') => break,
+                Some('\\') => {
+                    self.next_char();
+                    string.push(self.next_escape(row, col)?);
+                }
+                Some(&c) if c != '\n' => {
+                    self.next_char();
+                    string.push(c);
+                }
+                _ => {
+                    return Err(Diagnostic::new(
+                        "unterminated string literal".to_string(),
+                        self.span(row, col),
+                    ))
+                }
+            }
+        }
+        self.next_char();
+        Ok(string)
+    }
+}
+
+impl Iterator for Lexer<'_> {
+    type Item = Result<Token, Diagnostic>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.skip_space();
+            match self.iterator.peek()? {
+                '|' => self.skip_comment(),
+                _ => break,
+            }
+        }
+
+        let (row, col) = (self.row, self.col);
+        let kind = match *self.iterator.peek()? {
+            '\n' => {
+                self.next_char();
+                TokenKind::Newline
+            }
+            '<' | '>' => {
+                let c = self.next_char().unwrap();
+                if self.iterator.peek() != Some(&c) {
+                    return Some(Err(Diagnostic::new(
+                        format!("unexpected character: '{}'", c),
+                        self.span(row, col),
+                    )));
+                }
+                self.next_char();
+                match c {
+                    '<' => TokenKind::ShiftLeft,
+                    _ => TokenKind::ShiftRight,
+                }
+            }
+            c @ ('+' | '-' | '*' | '/' | '%' | '&' | '^' | '~' | '(' | ')' | ',') => {
+                self.next_char();
+                match c {
+                    '+' => TokenKind::Plus,
+                    '-' => TokenKind::Minus,
+                    '*' => TokenKind::Star,
+                    '/' => TokenKind::Slash,
+                    '%' => TokenKind::Percent,
+                    '&' => TokenKind::Ampersand,
+                    '^' => TokenKind::Caret,
+                    '~' => TokenKind::Tilde,
+                    '(' => TokenKind::LeftParen,
+                    ')' => TokenKind::RightParen,
+                    _ => TokenKind::Comma,
+                }
+            }
+            '@' => match self.next_name(row, col, "label") {
+                Ok(name) => TokenKind::Label(name),
+                Err(diagnostic) => return Some(Err(diagnostic)),
+            },
+            '.' => match self.next_name(row, col, "directive") {
+                Ok(name) => TokenKind::Directive(name),
+                Err(diagnostic) => return Some(Err(diagnostic)),
+            },
+            c if c.is_ascii_digit() => match self.next_number(row, col) {
+                Ok(value) => TokenKind::Number(value),
+                Err(diagnostic) => return Some(Err(diagnostic)),
+            },
+            '\'' => match self.next_character(row, col) {
+                Ok(value) => TokenKind::Number(value),
+                Err(diagnostic) => return Some(Err(diagnostic)),
+            },
+            '"' => match self.next_string(row, col) {
+                Ok(string) => TokenKind::String(string),
+                Err(diagnostic) => return Some(Err(diagnostic)),
+            },
+            c if is_identifier_start(&c) => TokenKind::Identifier(self.next_identifier()),
+            c => {
+                self.next_char();
+                return Some(Err(Diagnostic::new(
+                    format!("unexpected character: '{}'", c),
+                    self.span(row, col),
+                )));
+            }
+        };
+
+        Some(Ok(Token {
+            kind,
+            span: self.span(row, col),
+        }))
+    }
+}
+
+fn is_space(c: &char) -> bool {
+    c == &' ' || c == &'\t'
+}
+
+fn is_identifier_start(c: &char) -> bool {
+    c.is_alphabetic() || c == &'_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(source: &str) -> Vec<TokenKind> {
+        Lexer::new("test.asm", source)
+            .map(|token| token.unwrap().kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_tokens() {
+        assert_eq!(
+            kinds("@loop_1 push -5 | comment\n  goto loop_1\n"),
+            vec![
+                TokenKind::Label("loop_1".to_string()),
+                TokenKind::Identifier("push".to_string()),
+                TokenKind::Minus,
+                TokenKind::Number(5),
+                TokenKind::Newline,
+                TokenKind::Identifier("goto".to_string()),
+                TokenKind::Identifier("loop_1".to_string()),
+                TokenKind::Newline,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_identifiers() {
+        assert_eq!(
+            kinds("end2 _tmp x_y_3"),
+            vec![
+                TokenKind::Identifier("end2".to_string()),
+                TokenKind::Identifier("_tmp".to_string()),
+                TokenKind::Identifier("x_y_3".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!(
+            kinds("10 0xFF 0b1010 0o17 1_000 0x7f_ff 'A' '\\n' '\\''"),
+            vec![
+                TokenKind::Number(10),
+                TokenKind::Number(0xff),
+                TokenKind::Number(0b1010),
+                TokenKind::Number(0o17),
+                TokenKind::Number(1000),
+                TokenKind::Number(0x7fff),
+                TokenKind::Number(65),
+                TokenKind::Number(10),
+                TokenKind::Number(39),
+            ]
+        );
+
+        for source in ["0x", "0b12", "99999999999999999999", "'ab'", "'\\q'", "''"] {
+            let mut lexer = Lexer::new("test.asm", source);
+            assert!(lexer.next().unwrap().is_err(), "{}", source);
+        }
+
+        let mut lexer = Lexer::new("test.asm", "0x1_0000_0000_0000_0000");
+        assert_eq!(
+            lexer.next().unwrap().unwrap_err().message,
+            "literal 0x1_0000_0000_0000_0000 does not fit in a word (expected -32768..=32767)"
+        );
+    }
+
+    #[test]
+    fn test_strings() {
+        assert_eq!(
+            kinds(r#".include "lib/std.asm" "a\"b\n""#),
+            vec![
+                TokenKind::Directive("include".to_string()),
+                TokenKind::String("lib/std.asm".to_string()),
+                TokenKind::String("a\"b\n".to_string()),
+            ]
+        );
+
+        let mut lexer = Lexer::new("test.asm", "\"open\n\"");
+        assert!(lexer.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_operators() {
+        assert_eq!(
+            kinds("(a+1)*2 << ~b >> 3, c ^ d & e % f / g"),
+            vec![
+                TokenKind::LeftParen,
+                TokenKind::Identifier("a".to_string()),
+                TokenKind::Plus,
+                TokenKind::Number(1),
+                TokenKind::RightParen,
+                TokenKind::Star,
+                TokenKind::Number(2),
+                TokenKind::ShiftLeft,
+                TokenKind::Tilde,
+                TokenKind::Identifier("b".to_string()),
+                TokenKind::ShiftRight,
+                TokenKind::Number(3),
+                TokenKind::Comma,
+                TokenKind::Identifier("c".to_string()),
+                TokenKind::Caret,
+                TokenKind::Identifier("d".to_string()),
+                TokenKind::Ampersand,
+                TokenKind::Identifier("e".to_string()),
+                TokenKind::Percent,
+                TokenKind::Identifier("f".to_string()),
+                TokenKind::Slash,
+                TokenKind::Identifier("g".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let tokens: Vec<Token> = Lexer::new("test.asm", "push 12\n.var x")
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!((tokens[1].span.row, tokens[1].span.col), (1, 6));
+        assert_eq!(tokens[1].span.len, 2);
+        assert_eq!((tokens[3].span.row, tokens[3].span.col), (2, 1));
+        assert_eq!(tokens[3].span.len, 4);
+    }
+
+    #[test]
+    fn test_invalid_tokens() {
+        let mut lexer = Lexer::new("test.asm", "# 12ab @ <");
+        for _ in 0..4 {
+            assert!(lexer.next().unwrap().is_err());
+        }
+        assert!(lexer.next().is_none());
+    }
+}
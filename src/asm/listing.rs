@@ -0,0 +1,26 @@
+use std::ops::Range;
+
+/// Renders `source` next to the bytes of `ops`, pairs of a source row and the code it produced.
+pub fn render(name: &str, source: &str, code: &[u8], ops: &[(usize, Range<usize>)]) -> String {
+    let mut listing = format!("| {}\n", name);
+
+    for (i, text) in source.lines().enumerate() {
+        let row = i + 1;
+        let mut ops = ops.iter().filter(|(op_row, _)| *op_row == row);
+
+        let Some((_, first)) = ops.next() else {
+            listing.push_str(&format!("{: <20}{}\n", "", text));
+            continue;
+        };
+        let end = ops.next_back().map_or(first.end, |(_, last)| last.end);
+
+        let bytes = code[first.start..end]
+            .iter()
+            .map(|byte| format!("{:0>2x}", byte))
+            .collect::<Vec<String>>()
+            .join(" ");
+        listing.push_str(&format!("{:0>4}  {: <13} {}\n", first.start, bytes, text));
+    }
+
+    listing
+}
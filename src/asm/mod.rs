@@ -1,44 +1,175 @@
 pub mod assembler;
+pub mod ast;
+pub mod diagnostic;
+pub mod lexer;
+pub mod listing;
+pub mod parser;
 pub mod preprocessor;
 
-use assembler::{Assembler, Bytes};
-use preprocessor::Preprocessor;
+use assembler::Assembler;
 
 use std::env;
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Write};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use vmrs::Object;
 
-const DEBUG: bool = false;
+const USAGE: &str = "\
+Usage: asm [options] <input>...
 
-fn run(unicode: &str) -> Result<Bytes, String> {
-    let mut preprocessor = Preprocessor::new(&unicode, DEBUG);
-    let lables = preprocessor.preprocess()?;
+Options:
+    -o <out>            write the object file to <out>
+    -I <dir>            search <dir> for '.include' files
+    --listing <file>    write an address/bytes/source listing to <file>
+    -c                  keep relocation tables so the object can be linked
+    -g                  include a line table in the object file
+    --debug             trace the assembler passes";
 
-    let mut assembler = Assembler::new(&unicode, lables, DEBUG);
-    assembler.assemble()
+struct Options {
+    inputs: Vec<String>,
+    output: String,
+    listing: Option<String>,
+    include_paths: Vec<PathBuf>,
+    relocatable: bool,
+    lines: bool,
+    debug: bool,
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut inputs = Vec::new();
+        let mut output = None;
+        let mut listing = None;
+        let mut include_paths = Vec::new();
+        let mut relocatable = false;
+        let mut lines = false;
+        let mut debug = false;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-o" => {
+                    let path = args.next().ok_or("'-o' expects an output path")?;
+                    output = Some(path.clone());
+                }
+                "--listing" => {
+                    let path = args.next().ok_or("'--listing' expects a file path")?;
+                    listing = Some(path.clone());
+                }
+                "-I" => {
+                    let path = args.next().ok_or("'-I' expects a directory")?;
+                    include_paths.push(PathBuf::from(path));
+                }
+                flag if flag.starts_with("-I") => include_paths.push(PathBuf::from(&flag[2..])),
+                "-c" => relocatable = true,
+                "-g" => lines = true,
+                "--debug" => debug = true,
+                flag if flag.starts_with('-') => {
+                    return Err(format!("unknown option '{}'", flag));
+                }
+                input => inputs.push(input.to_string()),
+            }
+        }
+
+        let Some(first) = inputs.first() else {
+            return Err("no input files".to_string());
+        };
+        let output = output.unwrap_or_else(|| {
+            Path::new(first)
+                .with_extension("o")
+                .to_string_lossy()
+                .into_owned()
+        });
+
+        Ok(Self {
+            inputs,
+            output,
+            listing,
+            include_paths,
+            relocatable,
+            lines,
+            debug,
+        })
+    }
+}
+
+fn assemble(options: &Options, sources: &[String]) -> Result<(Object, String), String> {
+    let mut assembler = Assembler::new(options.debug, options.include_paths.clone());
+    assembler.set_relocatable(options.relocatable);
+    for (input, unicode) in options.inputs.iter().zip(sources) {
+        assembler.assemble(input, unicode);
+    }
+
+    let files = assembler.sources().to_vec();
+    let mut object = assembler.finish().map_err(|diagnostics| {
+        diagnostic::render_all(&diagnostics, |file| {
+            files
+                .iter()
+                .find(|source| source.name == file)
+                .map_or("", |source| source.unicode.as_str())
+        })
+    })?;
+    let lines = object.lines.take().unwrap_or_default();
+
+    let mut listing = String::new();
+    for source in &files {
+        let ops: Vec<(usize, Range<usize>)> = source
+            .lines
+            .iter()
+            .map(|&index| {
+                let end = lines
+                    .get(index + 1)
+                    .map_or(object.code.len(), |line| line.offset);
+                (lines[index].row, lines[index].offset..end)
+            })
+            .collect();
+        listing.push_str(&listing::render(
+            &source.name,
+            &source.unicode,
+            &object.code,
+            &ops,
+        ));
+    }
 
-    let mut file = File::open(&args[1].as_str()).expect("could not open src");
-    let mut buffer: Vec<u8> = Vec::new();
-    file.read_to_end(&mut buffer).expect("empty file supplied");
-    let unicode = String::from_utf8(buffer).expect("could not read unicode contents");
+    if options.lines {
+        object.lines = Some(lines);
+    }
+    Ok((object, listing))
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let mut sources = Vec::new();
+    for input in &options.inputs {
+        let unicode = fs::read_to_string(input)
+            .map_err(|error| format!("error: could not read '{}': {}", input, error))?;
+        sources.push(unicode);
+    }
+
+    let (object, listing) = assemble(options, &sources)?;
+
+    fs::write(&options.output, Vec::<u8>::from(&object))
+        .map_err(|error| format!("error: could not write '{}': {}", options.output, error))?;
+    if let Some(path) = &options.listing {
+        fs::write(path, listing)
+            .map_err(|error| format!("error: could not write '{}': {}", path, error))?;
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
 
-    match run(&unicode) {
+    let options = match Options::parse(&args) {
         Err(message) => {
-            eprintln!("ERROR: {}", message);
-            exit(1);
-        }
-        Ok(bytes) => {
-            let mut file = OpenOptions::new()
-                .write(true)
-                .create(true)
-                .open("test.o")
-                .expect("could not open out");
-            file.write(&bytes).expect("could not write to out");
+            eprintln!("error: {}\n\n{}", message, USAGE);
+            exit(2);
         }
+        Ok(options) => options,
+    };
+
+    if let Err(message) = run(&options) {
+        eprintln!("{}", message);
+        exit(1);
     }
 }
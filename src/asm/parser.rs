@@ -0,0 +1,328 @@
+use std::iter::Peekable;
+
+use crate::ast::{BinaryOp, Expr, ExprKind, Statement, UnaryOp};
+use crate::diagnostic::{Diagnostic, Span};
+use crate::lexer::{Lexer, Token, TokenKind};
+
+/*
+ * line      := {Label} [Identifier operands | Directive operands] (Newline | EOF)
+ * operands  := [expr {[','] expr}]
+ * expr      := unary {binary unary}
+ * unary     := ('-' | '~') unary | Number | String | Identifier | '(' expr ')'
+ *
+ * Directives that define something take a bare name as their first operand, so
+ * that `.equ A -1` does not read as `A - 1`.
+ */
+const DEFINITIONS: [&str; 4] = ["var", "equ", "const", "macro"];
+
+pub struct Parser<'a> {
+    tokens: Peekable<Lexer<'a>>,
+    statements: Vec<Statement>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(file: &'a str, unicode: &'a str) -> Self {
+        Self {
+            tokens: Lexer::new(file, unicode).peekable(),
+            statements: Vec::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    /// The next token on the current line, leaving the newline for the caller.
+    fn next_token(&mut self) -> Result<Option<Token>, Diagnostic> {
+        self.tokens.next_if(|token| !is_newline(token)).transpose()
+    }
+
+    fn expect_token(&mut self, after: &Span) -> Result<Token, Diagnostic> {
+        self.next_token()?.ok_or_else(|| {
+            Diagnostic::new(
+                "expected an expression".to_string(),
+                Span {
+                    col: after.col + after.len,
+                    len: 1,
+                    ..after.clone()
+                },
+            )
+        })
+    }
+
+    fn next_if_kind(&mut self, kind: &TokenKind) -> Option<Token> {
+        self.tokens
+            .next_if(|token| token.as_ref().is_ok_and(|token| token.kind == *kind))
+            .and_then(Result::ok)
+    }
+
+    fn peek_binary(&mut self) -> Option<BinaryOp> {
+        match self.tokens.peek() {
+            Some(Ok(token)) => binary_op(&token.kind),
+            _ => None,
+        }
+    }
+
+    fn next_unary(&mut self, token: Token) -> Result<Expr, Diagnostic> {
+        let kind = match token.kind {
+            TokenKind::Number(value) => ExprKind::Number(value),
+            TokenKind::String(string) => ExprKind::String(string),
+            TokenKind::Identifier(name) => ExprKind::Symbol(name),
+            TokenKind::Minus | TokenKind::Tilde => {
+                let op = match token.kind {
+                    TokenKind::Minus => UnaryOp::Neg,
+                    _ => UnaryOp::Not,
+                };
+                let next = self.expect_token(&token.span)?;
+                let operand = self.next_unary(next)?;
+                return Ok(Expr {
+                    span: join(&token.span, &operand.span),
+                    kind: ExprKind::Unary(op, Box::new(operand)),
+                });
+            }
+            TokenKind::LeftParen => {
+                let next = self.expect_token(&token.span)?;
+                let inner = self.next_expr(next, 0)?;
+                let close = self.next_if_kind(&TokenKind::RightParen).ok_or_else(|| {
+                    Diagnostic::new("expected ')'".to_string(), token.span.clone())
+                })?;
+                return Ok(Expr {
+                    span: join(&token.span, &close.span),
+                    ..inner
+                });
+            }
+            _ => {
+                return Err(Diagnostic::new(
+                    "expected an expression".to_string(),
+                    token.span,
+                ))
+            }
+        };
+        Ok(Expr {
+            kind,
+            span: token.span,
+        })
+    }
+
+    /// Precedence climbing: only operators binding at least as tight as `min` are consumed.
+    fn next_expr(&mut self, token: Token, min: u8) -> Result<Expr, Diagnostic> {
+        let mut lhs = self.next_unary(token)?;
+        while let Some(op) = self.peek_binary().filter(|op| op.precedence() >= min) {
+            let operator = self.tokens.next().unwrap()?;
+            let next = self.expect_token(&operator.span)?;
+            let rhs = self.next_expr(next, op.precedence() + 1)?;
+            lhs = Expr {
+                span: join(&lhs.span, &rhs.span),
+                kind: ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)),
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn next_operands(&mut self, definition: bool) -> Result<Vec<Expr>, Diagnostic> {
+        let mut operands = Vec::new();
+        while let Some(token) = self.next_token()? {
+            let operand = match token.kind {
+                TokenKind::Identifier(name) if definition && operands.is_empty() => Expr {
+                    kind: ExprKind::Symbol(name),
+                    span: token.span,
+                },
+                _ => self.next_expr(token, 0)?,
+            };
+            let comma = self.next_if_kind(&TokenKind::Comma);
+            operands.push(operand);
+
+            if let Some(comma) = comma {
+                if self.tokens.peek().is_none_or(is_newline) {
+                    return Err(Diagnostic::new(
+                        "expected an expression after ','".to_string(),
+                        comma.span,
+                    ));
+                }
+            }
+        }
+        Ok(operands)
+    }
+
+    fn parse_line(&mut self) -> Result<(), Diagnostic> {
+        while let Some(token) = self.next_token()? {
+            let statement = match token.kind {
+                TokenKind::Label(name) => Statement::Label {
+                    name,
+                    span: token.span,
+                },
+                TokenKind::Identifier(mnemonic) => Statement::Instruction {
+                    mnemonic,
+                    operands: self.next_operands(false)?,
+                    span: token.span,
+                },
+                TokenKind::Directive(name) => Statement::Directive {
+                    arguments: self.next_operands(DEFINITIONS.contains(&name.as_str()))?,
+                    name,
+                    span: token.span,
+                },
+                _ => {
+                    return Err(Diagnostic::new(
+                        "expected an instruction, label or directive".to_string(),
+                        token.span,
+                    ))
+                }
+            };
+            self.statements.push(statement);
+        }
+        Ok(())
+    }
+
+    pub fn parse(mut self) -> (Vec<Statement>, Vec<Diagnostic>) {
+        while self.tokens.peek().is_some() {
+            if let Err(diagnostic) = self.parse_line() {
+                self.diagnostics.push(diagnostic);
+                while self.tokens.next_if(|token| !is_newline(token)).is_some() {}
+            }
+            self.tokens.next(); // going over the newline
+        }
+        (self.statements, self.diagnostics)
+    }
+}
+
+/// Parses a whole source file, collecting every syntax error instead of stopping at the first.
+pub fn parse(file: &str, unicode: &str) -> (Vec<Statement>, Vec<Diagnostic>) {
+    Parser::new(file, unicode).parse()
+}
+
+fn is_newline(token: &Result<Token, Diagnostic>) -> bool {
+    matches!(
+        token,
+        Ok(Token {
+            kind: TokenKind::Newline,
+            ..
+        })
+    )
+}
+
+fn binary_op(kind: &TokenKind) -> Option<BinaryOp> {
+    match kind {
+        TokenKind::Plus => Some(BinaryOp::Add),
+        TokenKind::Minus => Some(BinaryOp::Sub),
+        TokenKind::Star => Some(BinaryOp::Mul),
+        TokenKind::Slash => Some(BinaryOp::Div),
+        TokenKind::Percent => Some(BinaryOp::Mod),
+        TokenKind::Ampersand => Some(BinaryOp::And),
+        TokenKind::Caret => Some(BinaryOp::Xor),
+        TokenKind::ShiftLeft => Some(BinaryOp::Shl),
+        TokenKind::ShiftRight => Some(BinaryOp::Shr),
+        _ => None,
+    }
+}
+
+fn join(start: &Span, end: &Span) -> Span {
+    Span {
+        len: end.col + end.len - start.col,
+        ..start.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(row: usize, col: usize, len: usize) -> Span {
+        Span {
+            file: "test.asm".to_string(),
+            row,
+            col,
+            len,
+            includes: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_statements() {
+        let (statements, diagnostics) = parse("test.asm", "@loop_1 push -5\n.var x 4\ngoto loop_1");
+        assert!(diagnostics.is_empty());
+        assert_eq!(
+            statements,
+            vec![
+                Statement::Label {
+                    name: "loop_1".to_string(),
+                    span: span(1, 1, 7),
+                },
+                Statement::Instruction {
+                    mnemonic: "push".to_string(),
+                    operands: vec![Expr {
+                        kind: ExprKind::Unary(
+                            UnaryOp::Neg,
+                            Box::new(Expr {
+                                kind: ExprKind::Number(5),
+                                span: span(1, 15, 1),
+                            }),
+                        ),
+                        span: span(1, 14, 2),
+                    }],
+                    span: span(1, 9, 4),
+                },
+                Statement::Directive {
+                    name: "var".to_string(),
+                    arguments: vec![
+                        Expr {
+                            kind: ExprKind::Symbol("x".to_string()),
+                            span: span(2, 6, 1),
+                        },
+                        Expr {
+                            kind: ExprKind::Number(4),
+                            span: span(2, 8, 1),
+                        },
+                    ],
+                    span: span(2, 1, 4),
+                },
+                Statement::Instruction {
+                    mnemonic: "goto".to_string(),
+                    operands: vec![Expr {
+                        kind: ExprKind::Symbol("loop_1".to_string()),
+                        span: span(3, 6, 6),
+                    }],
+                    span: span(3, 1, 4),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_precedence() {
+        let (statements, _) = parse("test.asm", ".equ A -1\npush 1 + 2 * (3 - x)");
+        let Statement::Directive { arguments, .. } = &statements[0] else {
+            panic!("expected a directive");
+        };
+        assert_eq!(arguments.len(), 2);
+
+        let Statement::Instruction { operands, .. } = &statements[1] else {
+            panic!("expected an instruction");
+        };
+        let ExprKind::Binary(BinaryOp::Add, _, rhs) = &operands[0].kind else {
+            panic!("expected an addition");
+        };
+        assert!(matches!(rhs.kind, ExprKind::Binary(BinaryOp::Mul, _, _)));
+        assert_eq!(operands[0].span, span(2, 6, 15));
+    }
+
+    #[test]
+    fn test_recovery() {
+        let (statements, diagnostics) = parse(
+            "test.asm",
+            "push 2 *\n5\npush 1 # 2\npush (1\npush 1,\nhalt\n",
+        );
+        let messages: Vec<&str> = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.message.as_str())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "expected an expression",
+                "expected an instruction, label or directive",
+                "unexpected character: '#'",
+                "expected ')'",
+                "expected an expression after ','",
+            ]
+        );
+        assert_eq!(statements.len(), 1);
+    }
+}
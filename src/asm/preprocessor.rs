@@ -1,100 +1,391 @@
 use std::collections::HashMap;
-use std::iter::Peekable;
-use std::mem::size_of;
-use std::str::Chars;
 
 use vmrs::OpKind;
-use vmrs::Word;
 
-pub struct Preprocessor<'a> {
-    iterator: Peekable<Chars<'a>>,
-    byte: Word,
-    debug: bool,
+use crate::ast::{Expr, ExprKind, Statement};
+use crate::diagnostic::{Diagnostic, Span};
+
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Statement>,
+}
+
+/* Expands `.macro name args ... .endm` definitions before labels are resolved */
+pub struct Preprocessor {
+    macros: HashMap<String, Macro>,
+    expansions: usize,
 }
 
-impl<'a> Preprocessor<'a> {
-    pub fn new(unicode: &'a str, debug: bool) -> Self {
+impl Preprocessor {
+    pub fn new() -> Self {
         Self {
-            iterator: unicode.chars().peekable(),
-            byte: 0,
-            debug,
+            macros: HashMap::new(),
+            expansions: 0,
         }
     }
 
-    fn skip_space(&mut self) {
-        while self.iterator.peek().is_some_and(|c| c.is_whitespace()) {
-            self.iterator.next();
+    /// Collects the macro definitions out of `statements` and expands every invocation.
+    /// Macros stay defined for the files that follow.
+    pub fn expand(
+        &mut self,
+        statements: Vec<Statement>,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Vec<Statement> {
+        let statements = self.collect(statements, diagnostics);
+
+        let mut expanded = Vec::new();
+        for statement in statements {
+            if let Err(diagnostic) = self.expand_statement(statement, 0, None, &mut expanded) {
+                diagnostics.push(diagnostic);
+            }
         }
+        expanded
     }
 
-    fn skip_comment(&mut self) {
-        while self.iterator.peek().is_some_and(|c| c != &'\n') {
-            self.iterator.next();
+    fn collect(
+        &mut self,
+        statements: Vec<Statement>,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Vec<Statement> {
+        let mut rest = Vec::new();
+        let mut statements = statements.into_iter();
+
+        while let Some(statement) = statements.next() {
+            match statement {
+                Statement::Directive {
+                    name,
+                    arguments,
+                    span,
+                } if name == "macro" => {
+                    let mut body = Vec::new();
+                    let mut terminated = false;
+                    for statement in statements.by_ref() {
+                        match &statement {
+                            Statement::Directive { name, .. } if name == "endm" => {
+                                terminated = true;
+                                break;
+                            }
+                            Statement::Directive { name, span, .. } if name == "macro" => {
+                                diagnostics.push(Diagnostic::new(
+                                    "macro definitions cannot be nested".to_string(),
+                                    span.clone(),
+                                ));
+                            }
+                            _ => body.push(statement),
+                        }
+                    }
+
+                    if !terminated {
+                        diagnostics.push(Diagnostic::new(
+                            "unterminated macro, expected '.endm'".to_string(),
+                            span,
+                        ));
+                        continue;
+                    }
+                    if let Err(diagnostic) = self.define(arguments, body, span) {
+                        diagnostics.push(diagnostic);
+                    }
+                }
+                Statement::Directive { name, span, .. } if name == "endm" => {
+                    diagnostics.push(Diagnostic::new(
+                        "'.endm' without a matching '.macro'".to_string(),
+                        span,
+                    ));
+                }
+                statement => rest.push(statement),
+            }
         }
+        rest
     }
 
-    fn next_identifier(&mut self) -> String {
-        let mut name = String::new();
-        while self.iterator.peek().is_some_and(|c| c.is_alphabetic()) {
-            name.push(self.iterator.next().unwrap());
+    fn define(
+        &mut self,
+        arguments: Vec<Expr>,
+        body: Vec<Statement>,
+        span: Span,
+    ) -> Result<(), Diagnostic> {
+        let mut names = Vec::new();
+        for argument in arguments {
+            match argument.kind {
+                ExprKind::Symbol(name) => names.push((name, argument.span)),
+                _ => {
+                    return Err(Diagnostic::new(
+                        "expected a parameter name".to_string(),
+                        argument.span,
+                    ))
+                }
+            }
         }
-        name
-    }
 
-    fn skip_word(&mut self) -> Result<(), String> {
-        let mut num = String::new();
-        while self
-            .iterator
-            .peek()
-            .is_some_and(|c| c.is_digit(10) || c == &'.')
-        {
-            num.push(self.iterator.next().unwrap());
+        let mut names = names.into_iter();
+        let Some((name, name_span)) = names.next() else {
+            return Err(Diagnostic::new(
+                "expected a macro name after '.macro'".to_string(),
+                span,
+            ));
+        };
+        if OpKind::try_from(name.to_uppercase()).is_ok() {
+            return Err(Diagnostic::new(
+                format!("macro '{}' shadows an instruction", name),
+                name_span,
+            ));
         }
-        num.parse::<Word>()
-            .map_err(|_| "could not parse number".to_string())?;
+        if self.macros.contains_key(&name) {
+            return Err(Diagnostic::new(
+                format!("duplicate macro: '{}'", name),
+                name_span,
+            ));
+        }
+
+        let params = names.map(|(param, _)| param).collect();
+        self.macros.insert(name, Macro { params, body });
         Ok(())
     }
 
-    fn skip_op(&mut self) -> Result<(), String> {
-        let kind: OpKind = self.next_identifier().to_uppercase().try_into()?;
-
-        self.skip_space();
+    /// Expanded statements are attributed to the outermost invocation `site`, so the line
+    /// table and listing point at the line that produced the code.
+    fn expand_statement(
+        &mut self,
+        mut statement: Statement,
+        depth: usize,
+        site: Option<&Span>,
+        expanded: &mut Vec<Statement>,
+    ) -> Result<(), Diagnostic> {
+        let invocation = match &statement {
+            Statement::Instruction { mnemonic, .. } => self.macros.get(mnemonic).cloned(),
+            _ => None,
+        };
+        let Some(definition) = invocation else {
+            if let Some(site) = site {
+                *span_mut(&mut statement) = site.clone();
+            }
+            expanded.push(statement);
+            return Ok(());
+        };
 
-        if kind == OpKind::Goto || kind == OpKind::Goif {
-            self.skip_space();
-            self.next_identifier();
-            self.byte += size_of::<Word>() as i16;
-        } else if kind.has_operand() {
-            self.skip_word()?;
-            self.byte += size_of::<Word>() as i16;
+        let Statement::Instruction {
+            mnemonic,
+            operands,
+            span,
+        } = statement
+        else {
+            unreachable!();
+        };
+        if depth >= MAX_DEPTH {
+            return Err(Diagnostic::new(
+                format!(
+                    "recursion limit of {} reached while expanding '{}'",
+                    MAX_DEPTH, mnemonic
+                ),
+                span,
+            ));
+        }
+        if operands.len() != definition.params.len() {
+            return Err(Diagnostic::new(
+                format!(
+                    "macro '{}' takes {} argument(s) but {} were given",
+                    mnemonic,
+                    definition.params.len(),
+                    operands.len()
+                ),
+                span,
+            ));
         }
-        self.byte += 1;
 
-        self.skip_space();
+        self.expansions += 1;
+        let arguments: HashMap<String, Expr> =
+            definition.params.into_iter().zip(operands).collect();
+        let labels: HashMap<String, String> = definition
+            .body
+            .iter()
+            .filter_map(|statement| match statement {
+                Statement::Label { name, .. } => Some((
+                    name.clone(),
+                    format!("__{}_{}_{}", mnemonic, self.expansions, name),
+                )),
+                _ => None,
+            })
+            .collect();
 
-        if self.debug {
-            println!("[DEBUG preprocess] {} | {:?}", self.byte, kind);
+        let site = site.unwrap_or(&span);
+        for statement in definition.body {
+            let statement = substitute(statement, &arguments, &labels);
+            self.expand_statement(statement, depth + 1, Some(site), expanded)?;
         }
         Ok(())
     }
+}
 
-    pub fn preprocess(&mut self) -> Result<HashMap<String, Word>, String> {
-        let mut labels = HashMap::new();
+impl Default for Preprocessor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-        while let Some(c) = self.iterator.peek() {
-            match c {
-                c if c.is_whitespace() => {
-                    self.iterator.next().unwrap();
-                }
-                '|' => self.skip_comment(),
-                '@' => {
-                    self.iterator.next().unwrap();
-                    labels.insert(self.next_identifier(), self.byte);
-                }
-                _ => self.skip_op()?,
-            }
+fn span_mut(statement: &mut Statement) -> &mut Span {
+    match statement {
+        Statement::Label { span, .. }
+        | Statement::Instruction { span, .. }
+        | Statement::Directive { span, .. } => span,
+    }
+}
+
+fn substitute(
+    statement: Statement,
+    arguments: &HashMap<String, Expr>,
+    labels: &HashMap<String, String>,
+) -> Statement {
+    let exprs = |exprs: Vec<Expr>| {
+        exprs
+            .into_iter()
+            .map(|expr| substitute_expr(expr, arguments, labels))
+            .collect()
+    };
+
+    match statement {
+        Statement::Label { name, span } => Statement::Label {
+            name: labels.get(&name).cloned().unwrap_or(name),
+            span,
+        },
+        Statement::Instruction {
+            mnemonic,
+            operands,
+            span,
+        } => Statement::Instruction {
+            mnemonic,
+            operands: exprs(operands),
+            span,
+        },
+        Statement::Directive {
+            name,
+            arguments,
+            span,
+        } => Statement::Directive {
+            name,
+            arguments: exprs(arguments),
+            span,
+        },
+    }
+}
+
+fn substitute_expr(
+    expr: Expr,
+    arguments: &HashMap<String, Expr>,
+    labels: &HashMap<String, String>,
+) -> Expr {
+    let kind = match expr.kind {
+        ExprKind::Symbol(name) => match (arguments.get(&name), labels.get(&name)) {
+            (Some(argument), _) => return argument.clone(),
+            (None, Some(label)) => ExprKind::Symbol(label.clone()),
+            (None, None) => ExprKind::Symbol(name),
+        },
+        ExprKind::Unary(op, operand) => {
+            ExprKind::Unary(op, Box::new(substitute_expr(*operand, arguments, labels)))
         }
+        ExprKind::Binary(op, lhs, rhs) => ExprKind::Binary(
+            op,
+            Box::new(substitute_expr(*lhs, arguments, labels)),
+            Box::new(substitute_expr(*rhs, arguments, labels)),
+        ),
+        kind => kind,
+    };
+    Expr {
+        kind,
+        span: expr.span,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+    use crate::parser;
+
+    fn expand(source: &str) -> (Vec<Statement>, Vec<String>) {
+        let (statements, mut diagnostics) = parser::parse("test.asm", source);
+        let statements = Preprocessor::new().expand(statements, &mut diagnostics);
+        let messages = diagnostics
+            .into_iter()
+            .map(|diagnostic| diagnostic.message)
+            .collect();
+        (statements, messages)
+    }
+
+    fn mnemonics(statements: &[Statement]) -> Vec<String> {
+        statements
+            .iter()
+            .map(|statement| match statement {
+                Statement::Label { name, .. } => format!("@{}", name),
+                Statement::Instruction { mnemonic, .. } => mnemonic.clone(),
+                Statement::Directive { name, .. } => format!(".{}", name),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_expansion() {
+        let source = "\
+            .macro countdown n\n\
+            push n\n\
+            @loop\n\
+            push 1\n\
+            sub\n\
+            copy\n\
+            goif loop\n\
+            .endm\n\
+            countdown 3\n\
+            countdown 5\n";
+        let (statements, messages) = expand(source);
+        assert!(messages.is_empty(), "{:?}", messages);
+        assert_eq!(
+            mnemonics(&statements),
+            vec![
+                "push",
+                "@__countdown_1_loop",
+                "push",
+                "sub",
+                "copy",
+                "goif",
+                "push",
+                "@__countdown_2_loop",
+                "push",
+                "sub",
+                "copy",
+                "goif",
+            ]
+        );
+
+        let Statement::Instruction { operands, span, .. } = &statements[5] else {
+            panic!("expected an instruction");
+        };
+        assert_eq!(
+            operands[0].kind,
+            ExprKind::Symbol("__countdown_1_loop".to_string())
+        );
+        assert_eq!(span.row, 9);
+
+        let mut assembler = Assembler::new(false, Vec::new());
+        assembler.assemble("test.asm", source);
+        assert!(assembler.finish().is_ok());
+    }
 
-        return Ok(labels);
+    #[test]
+    fn test_errors() {
+        let (_, messages) = expand(
+            ".macro forever\nforever\n.endm\nforever\n.macro push\n.endm\n.endm\n.macro two a b\n.endm\ntwo 1\n.macro open\n",
+        );
+        assert_eq!(
+            messages,
+            vec![
+                "macro 'push' shadows an instruction",
+                "'.endm' without a matching '.macro'",
+                "unterminated macro, expected '.endm'",
+                "recursion limit of 64 reached while expanding 'forever'",
+                "macro 'two' takes 2 argument(s) but 1 were given",
+            ]
+        );
     }
 }
@@ -0,0 +1,34 @@
+use std::env;
+use std::fs;
+use std::process::exit;
+use vmrs::{disassembler, Object};
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() < 2 {
+        eprintln!("Usage: {} <path>", args[0]);
+        exit(1);
+    }
+
+    let path = &args[1];
+    let result = fs::read(path);
+
+    if result.is_err() {
+        eprintln!("ERROR: could not read file");
+        exit(1);
+    }
+
+    let bytes = result.unwrap();
+    let source = Object::try_from(&bytes[..]).and_then(|object| {
+        disassembler::disassemble(&object.code, &object.data_ranges, object.symbols.as_ref())
+    });
+
+    match source {
+        Err(error) => {
+            eprintln!("ERROR: {}", error);
+            exit(1);
+        }
+        Ok(source) => print!("{}", source),
+    }
+}
@@ -0,0 +1,301 @@
+use std::collections::BTreeMap;
+use std::mem::size_of;
+
+use crate::error::VmError;
+use crate::object::{DataKind, DataRange};
+use crate::op::{Op, OpKind, Word};
+
+pub fn decode_at(program: &[u8], offset: usize) -> Result<Op, VmError> {
+    let byte = *program.get(offset).ok_or(VmError::SegmentationFault)?;
+    let kind = OpKind::try_from(byte)?;
+    let mut operand = None;
+
+    if kind.has_operand() {
+        let bytes = program
+            .get(offset + 1..offset + 1 + size_of::<Word>())
+            .ok_or(VmError::MissingOperand(offset + 1))?;
+        operand = Some(Word::from_be_bytes([bytes[0], bytes[1]]));
+    }
+
+    Ok(Op(kind, operand))
+}
+
+/// Decodes the instructions of `program`, stepping over the ranges that hold data.
+pub fn decode(program: &[u8], data: &[DataRange]) -> Result<Vec<(usize, Op)>, VmError> {
+    for range in data {
+        let word_sized = range.kind == DataKind::Byte || range.length % size_of::<Word>() == 0;
+        if range.length == 0 || range.offset + range.length > program.len() || !word_sized {
+            return Err(VmError::MalformedObject);
+        }
+    }
+
+    let mut ops = Vec::new();
+    let mut offset = 0;
+
+    while offset < program.len() {
+        if let Some(range) = data.iter().find(|range| range.offset == offset) {
+            offset += range.length;
+            continue;
+        }
+        let op = decode_at(program, offset)?;
+        ops.push((offset, op));
+        offset += Vec::<u8>::from(op).len();
+    }
+
+    Ok(ops)
+}
+
+pub type Labels = BTreeMap<usize, Vec<String>>;
+
+pub fn labels(
+    ops: &[(usize, Op)],
+    data: &[DataRange],
+    size: usize,
+    symbols: Option<&BTreeMap<String, Word>>,
+) -> Result<Labels, VmError> {
+    let is_boundary = |target: usize| {
+        target == size
+            || ops.iter().any(|(offset, _)| *offset == target)
+            || data.iter().any(|range| range.offset == target)
+    };
+    let mut labels = Labels::new();
+
+    for (name, &address) in symbols.into_iter().flatten() {
+        let target = usize::try_from(address).map_err(|_| VmError::InvalidAddress(address))?;
+        if !is_boundary(target) {
+            return Err(VmError::InvalidAddress(address));
+        }
+        labels.entry(target).or_default().push(name.clone());
+    }
+
+    let mut targets: Vec<usize> = Vec::new();
+    for (_, op) in ops {
+        if let Op(kind, Some(value)) = op {
+            if kind.has_label() {
+                let target =
+                    usize::try_from(*value).map_err(|_| VmError::InvalidAddress(*value))?;
+                if !is_boundary(target) {
+                    return Err(VmError::InvalidAddress(*value));
+                }
+                targets.push(target);
+            }
+        }
+    }
+
+    targets.sort();
+    targets.dedup();
+
+    let mut count = 0;
+    for target in targets {
+        if labels.contains_key(&target) {
+            continue;
+        }
+        let name = loop {
+            let name = format!("label{}", count);
+            count += 1;
+            if !symbols.is_some_and(|symbols| symbols.contains_key(&name)) {
+                break name;
+            }
+        };
+        labels.insert(target, vec![name]);
+    }
+
+    Ok(labels)
+}
+
+pub fn disassemble(
+    program: &[u8],
+    data: &[DataRange],
+    symbols: Option<&BTreeMap<String, Word>>,
+) -> Result<String, VmError> {
+    let ops = decode(program, data)?;
+    let labels = labels(&ops, data, program.len(), symbols)?;
+    let mut output = String::new();
+
+    let mut lines: Vec<(usize, String)> = ops
+        .iter()
+        .map(|(offset, op)| (*offset, format_op(op, &labels)))
+        .collect();
+    for range in data {
+        lines.append(&mut format_data(program, range));
+    }
+    lines.sort_by_key(|(offset, _)| *offset);
+    /* Long data lines widen the column, so every offset stays aligned */
+    let width = lines
+        .iter()
+        .map(|(_, text)| text.len() + 1)
+        .fold(20, usize::max);
+
+    for (offset, text) in &lines {
+        for label in labels.get(offset).into_iter().flatten() {
+            output.push_str(&format!("@{}\n", label));
+        }
+        output.push_str(&format!("    {: <width$}| {:0>4}\n", text, offset));
+    }
+
+    for label in labels.get(&program.len()).into_iter().flatten() {
+        output.push_str(&format!("@{}\n", label));
+    }
+
+    Ok(output)
+}
+
+/* Bytes of data eight to a line, words four to a line */
+fn format_data(program: &[u8], range: &DataRange) -> Vec<(usize, String)> {
+    let bytes = &program[range.offset..range.offset + range.length];
+    let (directive, width) = match range.kind {
+        DataKind::Byte => ("byte", 8),
+        DataKind::Word => ("word", 4 * size_of::<Word>()),
+    };
+
+    bytes
+        .chunks(width)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let values: Vec<String> = match range.kind {
+                DataKind::Byte => chunk.iter().map(u8::to_string).collect(),
+                DataKind::Word => chunk
+                    .chunks(size_of::<Word>())
+                    .map(|word| Word::from_be_bytes([word[0], word[1]]).to_string())
+                    .collect(),
+            };
+            (
+                range.offset + i * width,
+                format!(".{} {}", directive, values.join(", ")),
+            )
+        })
+        .collect()
+}
+
+pub fn format_op(op: &Op, labels: &Labels) -> String {
+    match op {
+        Op(kind, Some(value)) if kind.has_label() => {
+            match usize::try_from(*value)
+                .ok()
+                .and_then(|target| labels.get(&target))
+                .and_then(|names| names.first())
+            {
+                Some(label) => format!("{} {}", kind, label),
+                None => format!("{} {}", kind, value),
+            }
+        }
+        Op(kind, Some(value)) => format!("{} {}", kind, value),
+        Op(kind, None) => format!("{}", kind),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let ops = decode(&[OpKind::Push.into(), 0xff, 0xfe, OpKind::Echo.into()], &[]).unwrap();
+        assert_eq!(
+            ops,
+            vec![(0, Op(OpKind::Push, Some(-2))), (3, Op(OpKind::Echo, None))]
+        );
+    }
+
+    #[test]
+    fn test_decode_truncated_operand() {
+        assert_eq!(
+            decode(&[OpKind::Push.into(), 0x00], &[]),
+            Err(VmError::MissingOperand(1))
+        );
+    }
+
+    #[test]
+    fn test_disassemble_labels() {
+        let output = disassemble(
+            &[
+                OpKind::Push.into(),
+                0x00,
+                0x01,
+                OpKind::Goif.into(),
+                0x00,
+                0x07,
+                OpKind::Echo.into(),
+                OpKind::Goto.into(),
+                0x00,
+                0x00,
+            ],
+            &[],
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            output,
+            "@label0\n\
+             \x20   PUSH 1              | 0000\n\
+             \x20   GOIF label1         | 0003\n\
+             \x20   ECHO                | 0006\n\
+             @label1\n\
+             \x20   GOTO label0         | 0007\n"
+        );
+    }
+
+    #[test]
+    fn test_disassemble_symbols() {
+        let symbols = BTreeMap::from([
+            ("label0".to_string(), 0),
+            ("main".to_string(), 0),
+            ("end".to_string(), 4),
+        ]);
+        let output = disassemble(
+            &[OpKind::Goto.into(), 0x00, 0x03, OpKind::Halt.into()],
+            &[],
+            Some(&symbols),
+        )
+        .unwrap();
+        assert_eq!(
+            output,
+            "@label0\n\
+             @main\n\
+             \x20   GOTO label1         | 0000\n\
+             @label1\n\
+             \x20   HALT                | 0003\n\
+             @end\n"
+        );
+    }
+
+    #[test]
+    fn test_disassemble_misaligned_target() {
+        assert_eq!(
+            disassemble(&[OpKind::Goto.into(), 0x00, 0x01], &[], None),
+            Err(VmError::InvalidAddress(1))
+        );
+    }
+
+    #[test]
+    fn test_disassemble_data() {
+        let mut program = vec![OpKind::Push.into(), 0x00, 0x04, OpKind::Halt.into()];
+        program.extend([0x00, 0x10, 0xff, 0xfe]);
+        program.extend(b"hello, world\0");
+        let data = [
+            DataRange {
+                offset: 4,
+                length: 4,
+                kind: DataKind::Word,
+            },
+            DataRange {
+                offset: 8,
+                length: 13,
+                kind: DataKind::Byte,
+            },
+        ];
+        let symbols = BTreeMap::from([("table".to_string(), 4), ("message".to_string(), 8)]);
+
+        assert_eq!(
+            disassemble(&program, &data, Some(&symbols)).unwrap(),
+            "\x20   PUSH 4                                     | 0000\n\
+             \x20   HALT                                       | 0003\n\
+             @table\n\
+             \x20   .word 16, -2                               | 0004\n\
+             @message\n\
+             \x20   .byte 104, 101, 108, 108, 111, 44, 32, 119 | 0008\n\
+             \x20   .byte 111, 114, 108, 100, 0                | 0016\n"
+        );
+        assert_eq!(decode(&program[..10], &data), Err(VmError::MalformedObject));
+    }
+}
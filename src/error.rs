@@ -0,0 +1,117 @@
+use std::fmt;
+
+use crate::object::VERSION;
+use crate::op::{OpKind, Word};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmError {
+    /* Stack */
+    StackOverflow,
+    StackUnderflow,
+    ReturnStackOverflow,
+    ReturnStackUnderflow,
+    InvalidDepth(Word),
+
+    /* Decoding */
+    UnknownOpKind(u8),
+    UnknownMnemonic(String),
+    MissingOperand(usize),
+    IncorrectOp,
+    ProgramTooLarge(usize),
+    DataTooLarge(usize),
+
+    /* Object files */
+    InvalidMagic,
+    UnsupportedVersion(u16),
+    MalformedObject,
+    MalformedSnapshot,
+
+    /* Linking */
+    DuplicateSymbol(String),
+    UndefinedSymbol(String),
+    NotRelocatable,
+
+    /* Execution */
+    SegmentationFault,
+    InvalidAddress(Word),
+    MemoryOutOfBounds(Word),
+    DivisionByZero,
+    InvalidShift(Word),
+    InvalidInput(String),
+    EndOfInput,
+    ReadFailed(String),
+    WriteFailed(String),
+    UnknownSyscall(Word),
+    SyscallFailed(String),
+    Interrupted,
+    DeadlineExceeded,
+
+    /* An error raised while executing the instruction at `ip` */
+    Fault {
+        ip: usize,
+        op: Option<OpKind>,
+        depth: usize,
+        source: Box<VmError>,
+    },
+}
+
+impl VmError {
+    /// The underlying error, with any fault context stripped away.
+    pub fn kind(&self) -> &VmError {
+        match self {
+            VmError::Fault { source, .. } => source.kind(),
+            error => error,
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmError::StackOverflow => write!(f, "stack overflow"),
+            VmError::StackUnderflow => write!(f, "stack underflow"),
+            VmError::ReturnStackOverflow => write!(f, "return stack overflow"),
+            VmError::ReturnStackUnderflow => write!(f, "return stack underflow"),
+            VmError::InvalidDepth(depth) => write!(f, "invalid stack depth: {}", depth),
+            VmError::UnknownOpKind(value) => write!(f, "unknown binary op kind: '{}'", value),
+            VmError::UnknownMnemonic(value) => write!(f, "unknown string op kind: '{}'", value),
+            VmError::MissingOperand(ip) => write!(f, "could not extract word at {}", ip),
+            VmError::IncorrectOp => write!(f, "incorrect op code encountered"),
+            VmError::ProgramTooLarge(capacity) => {
+                write!(f, "a program must be under {}", capacity)
+            }
+            VmError::DataTooLarge(capacity) => {
+                write!(f, "initial data must fit in {} memory cells", capacity)
+            }
+            VmError::InvalidMagic => write!(f, "not a vmrs object file"),
+            VmError::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported object format version {} (expected at most {})",
+                version, VERSION
+            ),
+            VmError::MalformedObject => write!(f, "malformed object file"),
+            VmError::MalformedSnapshot => write!(f, "malformed or unsupported machine snapshot"),
+            VmError::DuplicateSymbol(name) => write!(f, "duplicate symbol: '{}'", name),
+            VmError::UndefinedSymbol(name) => write!(f, "undefined symbol: '{}'", name),
+            VmError::NotRelocatable => write!(f, "object file has no relocation information"),
+            VmError::SegmentationFault => write!(f, "segmentation fault"),
+            VmError::InvalidAddress(_) => write!(f, "invalid address"),
+            VmError::MemoryOutOfBounds(cell) => {
+                write!(f, "memory access out of bounds: {}", cell)
+            }
+            VmError::DivisionByZero => write!(f, "division by zero"),
+            VmError::InvalidShift(shift) => write!(f, "invalid shift amount: {}", shift),
+            VmError::InvalidInput(text) => write!(f, "invalid number in input: '{}'", text),
+            VmError::EndOfInput => write!(f, "unexpected end of input"),
+            VmError::ReadFailed(error) => write!(f, "could not read input: {}", error),
+            VmError::WriteFailed(error) => write!(f, "could not write output: {}", error),
+            VmError::UnknownSyscall(number) => write!(f, "unknown syscall: {}", number),
+            VmError::SyscallFailed(message) => write!(f, "syscall failed: {}", message),
+            VmError::Interrupted => write!(f, "execution was interrupted"),
+            VmError::DeadlineExceeded => write!(f, "execution deadline exceeded"),
+            VmError::Fault { source, .. } => write!(f, "{}", source),
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
@@ -0,0 +1,63 @@
+use std::io::{self, BufRead, BufReader, Cursor, Stdin, Stdout, Write};
+
+/// Where a `Machine` reads its input from and writes its output to.
+pub trait MachineIo {
+    fn input(&mut self) -> &mut dyn BufRead;
+    fn output(&mut self) -> &mut dyn Write;
+}
+
+pub struct StdIo {
+    stdin: BufReader<Stdin>,
+    stdout: Stdout,
+}
+
+impl StdIo {
+    pub fn new() -> Self {
+        Self {
+            stdin: BufReader::new(io::stdin()),
+            stdout: io::stdout(),
+        }
+    }
+}
+
+impl Default for StdIo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MachineIo for StdIo {
+    fn input(&mut self) -> &mut dyn BufRead {
+        &mut self.stdin
+    }
+
+    fn output(&mut self) -> &mut dyn Write {
+        &mut self.stdout
+    }
+}
+
+/// Feeds the machine a fixed input and collects everything it writes.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryIo {
+    pub input: Cursor<Vec<u8>>,
+    pub output: Vec<u8>,
+}
+
+impl MemoryIo {
+    pub fn new(input: impl Into<Vec<u8>>) -> Self {
+        Self {
+            input: Cursor::new(input.into()),
+            output: Vec::new(),
+        }
+    }
+}
+
+impl MachineIo for MemoryIo {
+    fn input(&mut self) -> &mut dyn BufRead {
+        &mut self.input
+    }
+
+    fn output(&mut self) -> &mut dyn Write {
+        &mut self.output
+    }
+}
@@ -1,6 +1,20 @@
+pub mod cancel;
+pub mod disassembler;
+pub mod error;
+pub mod io;
+pub mod linker;
 pub mod machine;
+pub mod object;
 pub mod op;
+pub mod snapshot;
 pub mod stack;
+pub mod syscall;
 
-pub use machine::Machine;
+pub use cancel::CancelToken;
+pub use error::VmError;
+pub use io::{MachineIo, MemoryIo, StdIo};
+pub use machine::{Machine, RunOutcome};
+pub use object::Object;
 pub use op::{Op, OpKind, Word};
+pub use snapshot::Snapshot;
+pub use syscall::{Syscall, SyscallContext};
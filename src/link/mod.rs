@@ -0,0 +1,84 @@
+use std::env;
+use std::fs;
+use std::process::exit;
+use vmrs::{linker, Object};
+
+const USAGE: &str = "\
+Usage: link [options] <object>...
+
+Options:
+    -o <out>            write the linked image to <out> (default: a.out)";
+
+struct Options {
+    inputs: Vec<String>,
+    output: String,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut inputs = Vec::new();
+        let mut output = None;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-o" => {
+                    let path = args.next().ok_or("'-o' expects an output path")?;
+                    output = Some(path.clone());
+                }
+                flag if flag.starts_with('-') => {
+                    return Err(format!("unknown option '{}'", flag));
+                }
+                input => inputs.push(input.to_string()),
+            }
+        }
+
+        if inputs.is_empty() {
+            return Err("no input files".to_string());
+        }
+
+        Ok(Self {
+            inputs,
+            output: output.unwrap_or_else(|| "a.out".to_string()),
+        })
+    }
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let mut objects = Vec::new();
+    for input in &options.inputs {
+        let bytes = fs::read(input)
+            .map_err(|error| format!("error: could not read '{}': {}", input, error))?;
+        let object =
+            Object::try_from(&bytes[..]).map_err(|error| format!("error: {}: {}", input, error))?;
+        if object.link.is_none() {
+            return Err(format!(
+                "error: {}: object file has no relocation information, assemble it with '-c'",
+                input
+            ));
+        }
+        objects.push(object);
+    }
+
+    let image = linker::link(&objects).map_err(|error| format!("error: {}", error))?;
+
+    fs::write(&options.output, Vec::<u8>::from(&image))
+        .map_err(|error| format!("error: could not write '{}': {}", options.output, error))
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let options = match Options::parse(&args) {
+        Err(message) => {
+            eprintln!("error: {}\n\n{}", message, USAGE);
+            exit(2);
+        }
+        Ok(options) => options,
+    };
+
+    if let Err(message) = run(&options) {
+        eprintln!("{}", message);
+        exit(1);
+    }
+}
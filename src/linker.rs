@@ -0,0 +1,257 @@
+use std::collections::BTreeMap;
+use std::mem::size_of;
+
+use crate::error::VmError;
+use crate::machine::PROGRAM_CAPACITY;
+use crate::object::{DataRange, Link, Object, Segment};
+use crate::op::Word;
+
+/// Where each object ends up in the linked image.
+struct Placement {
+    code: Word,
+    memory: Word,
+}
+
+fn rebase(address: Word, base: Word) -> Result<Word, VmError> {
+    address
+        .checked_add(base)
+        .ok_or(VmError::ProgramTooLarge(PROGRAM_CAPACITY))
+}
+
+fn add_to_word(code: &mut [u8], offset: usize, value: Word) -> Result<(), VmError> {
+    let bytes = code
+        .get_mut(offset..offset + size_of::<Word>())
+        .ok_or(VmError::MalformedObject)?;
+    let word = rebase(Word::from_be_bytes([bytes[0], bytes[1]]), value)?;
+    bytes.copy_from_slice(&word.to_be_bytes());
+    Ok(())
+}
+
+fn place(objects: &[Object]) -> Result<Vec<(Placement, &Link)>, VmError> {
+    let (mut code, mut memory): (Word, Word) = (0, 0);
+    let mut placements = Vec::new();
+
+    for object in objects {
+        let link = object.link.as_ref().ok_or(VmError::NotRelocatable)?;
+        placements.push((Placement { code, memory }, link));
+
+        let end = code as usize + object.code.len();
+        if end > PROGRAM_CAPACITY {
+            return Err(VmError::ProgramTooLarge(PROGRAM_CAPACITY));
+        }
+        code = end as Word;
+        memory = memory
+            .checked_add(link.cells.max(object.data.len() as Word))
+            .ok_or(VmError::DataTooLarge(Word::MAX as usize))?;
+    }
+    Ok(placements)
+}
+
+/// Lays the objects out one after the other and resolves the imports of each against
+/// the exports of all of them, producing a single executable image.
+pub fn link(objects: &[Object]) -> Result<Object, VmError> {
+    let placements = place(objects)?;
+
+    let mut exports = BTreeMap::new();
+    for (placement, link) in &placements {
+        for (name, &address) in &link.exports {
+            if exports
+                .insert(name.clone(), rebase(address, placement.code)?)
+                .is_some()
+            {
+                return Err(VmError::DuplicateSymbol(name.clone()));
+            }
+        }
+    }
+
+    let mut image = Object::new(Vec::new());
+    let mut symbols = BTreeMap::new();
+
+    for (object, (placement, link)) in objects.iter().zip(&placements) {
+        let mut code = object.code.clone();
+        for relocation in &link.relocations {
+            let base = match relocation.segment {
+                Segment::Code => placement.code,
+                Segment::Memory => placement.memory,
+            };
+            add_to_word(&mut code, relocation.offset, base)?;
+        }
+        for import in &link.imports {
+            let address = exports
+                .get(&import.name)
+                .ok_or_else(|| VmError::UndefinedSymbol(import.name.clone()))?;
+            add_to_word(&mut code, import.offset, *address)?;
+        }
+        image.code.append(&mut code);
+        for range in &object.data_ranges {
+            image.data_ranges.push(DataRange {
+                offset: range.offset + placement.code as usize,
+                ..range.clone()
+            });
+        }
+
+        if !object.data.is_empty() {
+            let start = placement.memory as usize;
+            image.data.resize(start, 0);
+            image.data.extend(&object.data);
+        }
+
+        /* Local labels of different objects may clash, the first one wins */
+        for (name, &address) in object.symbols.iter().flatten() {
+            if !symbols.contains_key(name) {
+                symbols.insert(name.clone(), rebase(address, placement.code)?);
+            }
+        }
+    }
+
+    if objects.iter().any(|object| object.symbols.is_some()) {
+        image.symbols = Some(symbols);
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::{DataKind, Import, Relocation};
+    use crate::op::OpKind;
+
+    fn object(code: Vec<u8>, link: Link) -> Object {
+        Object {
+            link: Some(link),
+            ..Object::new(code)
+        }
+    }
+
+    #[test]
+    fn test_link() {
+        /* main: call print; goto 0 (relocated); load 0 (memory relocated) */
+        let main = object(
+            vec![
+                OpKind::Call.into(),
+                0x00,
+                0x00,
+                OpKind::Goto.into(),
+                0x00,
+                0x00,
+                OpKind::Load.into(),
+                0x00,
+                0x00,
+            ],
+            Link {
+                cells: 1,
+                relocations: vec![Relocation {
+                    offset: 4,
+                    segment: Segment::Code,
+                }],
+                imports: vec![Import {
+                    offset: 1,
+                    name: "print".to_string(),
+                }],
+                ..Link::default()
+            },
+        );
+        /* lib: @print echo; load 1 (memory relocated); ret */
+        let mut lib = object(
+            vec![
+                OpKind::Echo.into(),
+                OpKind::Load.into(),
+                0x00,
+                0x01,
+                OpKind::Ret.into(),
+            ],
+            Link {
+                cells: 2,
+                relocations: vec![Relocation {
+                    offset: 2,
+                    segment: Segment::Memory,
+                }],
+                exports: BTreeMap::from([("print".to_string(), 0)]),
+                ..Link::default()
+            },
+        );
+
+        lib.data_ranges = vec![DataRange {
+            offset: 4,
+            length: 1,
+            kind: DataKind::Byte,
+        }];
+
+        let image = link(&[main, lib]).unwrap();
+        assert_eq!(
+            image.code,
+            vec![
+                OpKind::Call.into(),
+                0x00,
+                0x09,
+                OpKind::Goto.into(),
+                0x00,
+                0x00,
+                OpKind::Load.into(),
+                0x00,
+                0x00,
+                OpKind::Echo.into(),
+                OpKind::Load.into(),
+                0x00,
+                0x02,
+                OpKind::Ret.into(),
+            ]
+        );
+        assert_eq!(image.data_ranges[0].offset, 13);
+        assert!(image.link.is_none());
+    }
+
+    #[test]
+    fn test_symbol_errors() {
+        let exporting = || {
+            object(
+                vec![OpKind::Halt.into()],
+                Link {
+                    exports: BTreeMap::from([("main".to_string(), 0)]),
+                    ..Link::default()
+                },
+            )
+        };
+        assert_eq!(
+            link(&[exporting(), exporting()]),
+            Err(VmError::DuplicateSymbol("main".to_string()))
+        );
+
+        let importing = object(
+            vec![OpKind::Goto.into(), 0x00, 0x00],
+            Link {
+                imports: vec![Import {
+                    offset: 1,
+                    name: "missing".to_string(),
+                }],
+                ..Link::default()
+            },
+        );
+        assert_eq!(
+            link(&[importing]),
+            Err(VmError::UndefinedSymbol("missing".to_string()))
+        );
+
+        assert_eq!(
+            link(&[Object::new(vec![OpKind::Halt.into()])]),
+            Err(VmError::NotRelocatable)
+        );
+    }
+
+    #[test]
+    fn test_program_too_large() {
+        let half = object(
+            vec![OpKind::Halt.into(); PROGRAM_CAPACITY / 2],
+            Link::default(),
+        );
+        assert!(link(&[half.clone(), half.clone()]).is_ok());
+        assert_eq!(
+            link(&[
+                half.clone(),
+                half.clone(),
+                object(vec![OpKind::Halt.into()], Link::default())
+            ]),
+            Err(VmError::ProgramTooLarge(PROGRAM_CAPACITY))
+        );
+    }
+}
@@ -1,23 +1,104 @@
+use crate::cancel::CancelToken;
+use crate::error::VmError;
+use crate::io::{MachineIo, StdIo};
+use crate::object::Object;
 use crate::op::{Op, OpKind, Word};
+use crate::snapshot::Snapshot;
 use crate::stack::Stack;
+use crate::syscall::{Syscall, SyscallContext};
+use std::collections::HashMap;
 use std::mem::size_of;
+use std::time::Instant;
 
-const PROGRAM_CAPACITY: usize = 1 << 10;
+pub const PROGRAM_CAPACITY: usize = 1 << 10;
+const RETURN_STACK_CAPACITY: usize = 1 << 8;
+pub const MEMORY_SIZE: usize = 1 << 10;
+/* How many instructions run between checks for cancellation and the deadline */
+const INTERRUPT_INTERVAL: usize = 1 << 10;
 
-pub struct Machine {
+/// How a bounded run ended, a machine that ran out of fuel can be run again to resume.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunOutcome {
+    Halted,
+    OutOfFuel,
+    Trapped(VmError),
+}
+
+pub struct Machine<IO: MachineIo = StdIo> {
     stack: Stack,
+    returns: Vec<usize>,
+    memory: Vec<Word>,
     program: [u8; PROGRAM_CAPACITY],
     program_size: usize,
+    io: IO,
+    syscalls: HashMap<Word, Syscall>,
+    cancel: CancelToken,
+    deadline: Option<Instant>,
     halted: bool,
     ip: usize,
 }
 
 impl Machine {
-    pub fn try_new(input: &[u8]) -> Result<Self, String> {
+    pub fn try_new(input: &[u8]) -> Result<Self, VmError> {
+        Self::try_with_memory(input, MEMORY_SIZE)
+    }
+
+    pub fn try_from_object(object: &Object) -> Result<Self, VmError> {
+        if object.data.len() > MEMORY_SIZE {
+            return Err(VmError::DataTooLarge(MEMORY_SIZE));
+        }
+        if let Some(import) = object.link.iter().flat_map(|link| &link.imports).next() {
+            return Err(VmError::UndefinedSymbol(import.name.clone()));
+        }
+
+        let mut machine = Self::try_new(&object.code)?;
+        machine.memory[..object.data.len()].copy_from_slice(&object.data);
+        Ok(machine)
+    }
+
+    /// Rebuilds a machine from `snapshot`, with stdio and no syscalls registered.
+    pub fn try_from_snapshot(snapshot: &Snapshot) -> Result<Self, VmError> {
+        let program_size = snapshot.program.len();
+        if program_size > PROGRAM_CAPACITY {
+            return Err(VmError::ProgramTooLarge(PROGRAM_CAPACITY));
+        }
+        if snapshot.returns.len() > RETURN_STACK_CAPACITY
+            || snapshot.ip > program_size
+            || snapshot
+                .returns
+                .iter()
+                .any(|&address| address > program_size)
+        {
+            return Err(VmError::MalformedSnapshot);
+        }
+
+        let mut program = [0; PROGRAM_CAPACITY];
+        program[..program_size].copy_from_slice(&snapshot.program);
+        let mut stack = Stack::new();
+        for &word in &snapshot.stack {
+            stack.push(word).map_err(|_| VmError::MalformedSnapshot)?;
+        }
+
+        Ok(Self {
+            stack,
+            returns: snapshot.returns.clone(),
+            memory: snapshot.memory.clone(),
+            program,
+            program_size,
+            io: StdIo::new(),
+            syscalls: HashMap::new(),
+            cancel: CancelToken::new(),
+            deadline: None,
+            ip: snapshot.ip,
+            halted: snapshot.halted,
+        })
+    }
+
+    pub fn try_with_memory(input: &[u8], memory_size: usize) -> Result<Self, VmError> {
         let mut program_size = input.len();
 
         if program_size > PROGRAM_CAPACITY {
-            return Err(format!("a program must be under {}", PROGRAM_CAPACITY));
+            return Err(VmError::ProgramTooLarge(PROGRAM_CAPACITY));
         }
 
         let mut program = [0; PROGRAM_CAPACITY];
@@ -29,99 +110,462 @@ impl Machine {
             .last()
             .is_some_and(|value| *value != OpKind::Halt.into())
         {
+            /* The appended HALT needs a byte of its own */
+            if program_size == PROGRAM_CAPACITY {
+                return Err(VmError::ProgramTooLarge(PROGRAM_CAPACITY));
+            }
             program[program_size] = OpKind::Halt.into();
             program_size += 1;
         }
 
         Ok(Self {
             stack: Stack::new(),
+            returns: Vec::with_capacity(RETURN_STACK_CAPACITY),
+            memory: vec![0; memory_size],
             program,
             program_size,
+            io: StdIo::new(),
+            syscalls: HashMap::new(),
+            cancel: CancelToken::new(),
+            deadline: None,
             ip: 0,
             halted: false,
         })
     }
+}
+
+impl<IO: MachineIo> Machine<IO> {
+    /// Swaps stdin and stdout for `io`, keeping the rest of the state.
+    pub fn with_io<T: MachineIo>(self, io: T) -> Machine<T> {
+        Machine {
+            stack: self.stack,
+            returns: self.returns,
+            memory: self.memory,
+            program: self.program,
+            program_size: self.program_size,
+            io,
+            syscalls: self.syscalls,
+            cancel: self.cancel,
+            deadline: self.deadline,
+            halted: self.halted,
+            ip: self.ip,
+        }
+    }
+
+    /// Makes `SYSCALL number` call `syscall`, replacing any previous registration.
+    pub fn register_syscall(
+        &mut self,
+        number: Word,
+        syscall: impl FnMut(&mut SyscallContext) -> Result<(), VmError> + Send + 'static,
+    ) {
+        self.syscalls.insert(number, Box::new(syscall));
+    }
+
+    /// A handle that makes `run` and `run_for` stop with `VmError::Interrupted`.
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
 
-    pub fn run(&mut self, debug: bool) -> Result<(), String> {
+    /// Makes `run` and `run_for` stop with `VmError::DeadlineExceeded` past `deadline`.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            program: self.program().to_vec(),
+            ip: self.ip,
+            halted: self.halted,
+            stack: self.stack.as_slice().to_vec(),
+            returns: self.returns.clone(),
+            memory: self.memory.clone(),
+        }
+    }
+
+    pub fn memory(&self) -> &[Word] {
+        &self.memory
+    }
+
+    pub fn io(&self) -> &IO {
+        &self.io
+    }
+
+    pub fn io_mut(&mut self) -> &mut IO {
+        &mut self.io
+    }
+
+    pub fn run(&mut self, debug: bool) -> Result<(), VmError> {
+        let mut steps = 0;
         while !self.halted {
+            if steps % INTERRUPT_INTERVAL == 0 {
+                self.check_interrupts()?;
+            }
             self.exeucte(debug)?;
+            steps += 1;
+        }
+        Ok(())
+    }
+
+    /// Executes at most `max_steps` instructions.
+    pub fn run_for(&mut self, max_steps: usize) -> RunOutcome {
+        for steps in 0..max_steps {
+            if self.halted {
+                break;
+            }
+            let result = match steps % INTERRUPT_INTERVAL {
+                0 => self.check_interrupts(),
+                _ => Ok(()),
+            };
+            if let Err(error) = result.and_then(|_| self.exeucte(false)) {
+                return RunOutcome::Trapped(error);
+            }
+        }
+
+        match self.halted {
+            true => RunOutcome::Halted,
+            false => RunOutcome::OutOfFuel,
+        }
+    }
+
+    pub fn step(&mut self) -> Result<(), VmError> {
+        self.exeucte(false)
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn program(&self) -> &[u8] {
+        &self.program[..self.program_size]
+    }
+
+    pub fn stack(&self) -> &Stack {
+        &self.stack
+    }
+
+    pub fn stack_mut(&mut self) -> &mut Stack {
+        &mut self.stack
+    }
+
+    pub fn call_depth(&self) -> usize {
+        self.returns.len()
+    }
+
+    fn check_interrupts(&self) -> Result<(), VmError> {
+        if self.cancel.is_cancelled() {
+            return Err(VmError::Interrupted);
+        }
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Err(VmError::DeadlineExceeded);
         }
         Ok(())
     }
 
-    fn exeucte(&mut self, debug: bool) -> Result<(), String> {
-        if self.ip > self.program_size {
-            return Err("segmentation fault".to_string());
+    fn exeucte(&mut self, debug: bool) -> Result<(), VmError> {
+        let ip = self.ip;
+        if ip >= self.program_size {
+            return Err(self.fault(ip, None, VmError::SegmentationFault));
         }
 
-        let op = self.parse_op()?;
+        let op = self
+            .parse_op()
+            .map_err(|error| self.fault(ip, None, error))?;
         if debug {
-            println!(
-                "[DEBUG] {:0>3} | {: <20} | stack = {}",
+            let trace = format!(
+                "[DEBUG] {:0>3} | {: <20} | stack = {}\n",
                 self.ip,
                 format!("{:?}", op),
                 self.stack
             );
+            self.write(trace.as_bytes())
+                .map_err(|error| self.fault(ip, Some(op.0), error))?;
         }
 
+        let kind = op.0;
+        self.apply(op)
+            .map_err(|error| self.fault(ip, Some(kind), error))
+    }
+
+    fn apply(&mut self, op: Op) -> Result<(), VmError> {
         match op {
             Op(OpKind::Push, Some(word)) => self.stack.push(word)?,
             Op(OpKind::Pop, None) => drop(self.stack.pop()?),
-            Op(OpKind::Echo, None) => println!("{}", self.stack.head()?),
-            Op(OpKind::Add, None) => {
-                let a = self.stack.pop()?;
-                let b = self.stack.pop()?;
-                self.stack.push(b + a)?;
-            }
-            Op(OpKind::Sub, None) => {
-                let a = self.stack.pop()?;
-                let b = self.stack.pop()?;
-                self.stack.push(b - a)?;
+            Op(OpKind::Echo, None) => {
+                let head = self.stack.head()?;
+                self.write(format!("{}\n", head).as_bytes())?;
             }
-            Op(OpKind::Mul, None) => {
+            Op(OpKind::Add, None) => self.binary(|b, a| Ok(b.wrapping_add(a)))?,
+            Op(OpKind::Sub, None) => self.binary(|b, a| Ok(b.wrapping_sub(a)))?,
+            Op(OpKind::Mul, None) => self.binary(|b, a| Ok(b.wrapping_mul(a)))?,
+            Op(OpKind::Div, None) => self.binary(|b, a| match a {
+                0 => Err(VmError::DivisionByZero),
+                _ => Ok(b.wrapping_div(a)),
+            })?,
+            Op(OpKind::Mod, None) => self.binary(|b, a| match a {
+                0 => Err(VmError::DivisionByZero),
+                _ => Ok(b.wrapping_rem(a)),
+            })?,
+            Op(OpKind::Eq, None) => self.binary(|b, a| Ok((b == a).into()))?,
+            Op(OpKind::Ne, None) => self.binary(|b, a| Ok((b != a).into()))?,
+            Op(OpKind::Lt, None) => self.binary(|b, a| Ok((b < a).into()))?,
+            Op(OpKind::Le, None) => self.binary(|b, a| Ok((b <= a).into()))?,
+            Op(OpKind::Gt, None) => self.binary(|b, a| Ok((b > a).into()))?,
+            Op(OpKind::Ge, None) => self.binary(|b, a| Ok((b >= a).into()))?,
+            Op(OpKind::And, None) => self.binary(|b, a| Ok(b & a))?,
+            Op(OpKind::Or, None) => self.binary(|b, a| Ok(b | a))?,
+            Op(OpKind::Xor, None) => self.binary(|b, a| Ok(b ^ a))?,
+            Op(OpKind::Not, None) => {
                 let a = self.stack.pop()?;
-                let b = self.stack.pop()?;
-                self.stack.push(b * a)?;
+                self.stack.push(!a)?;
             }
-            Op(OpKind::Div, None) => {
-                let a = self.stack.pop()?;
-                let b = self.stack.pop()?;
-                if a == 0 {
-                    return Err("division by zero".to_string());
+            Op(OpKind::Shl, None) => self.binary(|b, a| {
+                u32::try_from(a)
+                    .ok()
+                    .and_then(|shift| b.checked_shl(shift))
+                    .ok_or(VmError::InvalidShift(a))
+            })?,
+            Op(OpKind::Shr, None) => self.binary(|b, a| {
+                u32::try_from(a)
+                    .ok()
+                    .and_then(|shift| b.checked_shr(shift))
+                    .ok_or(VmError::InvalidShift(a))
+            })?,
+            Op(OpKind::Goto, Some(value)) => self.ip = self.address(value)?,
+            Op(OpKind::Goif, Some(value)) => match self.stack.pop()? {
+                0 => {}
+                _ => self.ip = self.address(value)?,
+            },
+            Op(OpKind::Goifz, Some(value)) => {
+                if self.stack.pop()? == 0 {
+                    self.ip = self.address(value)?;
                 }
-                self.stack.push(b / a)?;
             }
-            Op(OpKind::Goto, Some(value)) => {
-                let address = usize::try_from(value).map_err(|_| "invalid address".to_string())?;
-                if address > self.program_size {
-                    return Err("segmentation fault".to_string());
+            Op(OpKind::Call, Some(value)) => {
+                let address = self.address(value)?;
+                if self.returns.len() >= RETURN_STACK_CAPACITY {
+                    return Err(VmError::ReturnStackOverflow);
                 }
+                self.returns.push(self.ip);
                 self.ip = address;
             }
-            Op(OpKind::Goif, Some(value)) => match self.stack.pop()? {
-                0 => {}
-                _ => {
-                    let address =
-                        usize::try_from(value).map_err(|_| "invalid address".to_string())?;
-                    if address > self.program_size {
-                        return Err("segmentation fault".to_string());
-                    }
-                    self.ip = address;
-                }
-            },
+            Op(OpKind::Ret, None) => {
+                self.ip = self.returns.pop().ok_or(VmError::ReturnStackUnderflow)?;
+            }
+            Op(OpKind::Load, Some(value)) => {
+                let cell = self.cell(value)?;
+                self.stack.push(self.memory[cell])?;
+            }
+            Op(OpKind::Store, Some(value)) => {
+                let cell = self.cell(value)?;
+                self.memory[cell] = self.stack.pop()?;
+            }
+            Op(OpKind::Loadi, None) => {
+                let value = self.stack.pop()?;
+                let cell = self.cell(value)?;
+                self.stack.push(self.memory[cell])?;
+            }
+            Op(OpKind::Storei, None) => {
+                let value = self.stack.pop()?;
+                let cell = self.cell(value)?;
+                self.memory[cell] = self.stack.pop()?;
+            }
+            Op(OpKind::Loadp, None) => {
+                let value = self.stack.pop()?;
+                let word = self.program_word(value)?;
+                self.stack.push(word)?;
+            }
+            Op(OpKind::Putc, None) => {
+                let value = self.stack.pop()?;
+                self.write(&[value as u8])?;
+            }
+            Op(OpKind::Putn, None) => {
+                let value = self.stack.pop()?;
+                self.write(value.to_string().as_bytes())?;
+            }
+            Op(OpKind::Prints, None) => {
+                let value = self.stack.pop()?;
+                let string = self.memory_string(value)?;
+                self.write(&string)?;
+            }
+            Op(OpKind::Printp, None) => {
+                let value = self.stack.pop()?;
+                let string = self.program_string(value)?;
+                self.write(&string)?;
+            }
+            Op(OpKind::Getc, None) => {
+                let byte = self.next_input()?;
+                self.stack.push(byte.map_or(-1, Word::from))?;
+            }
+            Op(OpKind::Readn, None) => {
+                let number = self.read_number()?;
+                self.stack.push(number)?;
+            }
+            Op(OpKind::Syscall, Some(number)) => {
+                let syscall = self
+                    .syscalls
+                    .get_mut(&number)
+                    .ok_or(VmError::UnknownSyscall(number))?;
+                syscall(&mut SyscallContext::new(&mut self.stack, &mut self.memory))?;
+            }
             Op(OpKind::Copy, None) => {
                 let head = self.stack.head()?;
                 self.stack.push(head)?;
             }
+            Op(OpKind::Swap, None) => self.stack.swap()?,
+            Op(OpKind::Over, None) => {
+                let second = self.stack.peek_at(1)?;
+                self.stack.push(second)?;
+            }
+            Op(OpKind::Rot, None) => self.stack.rot()?,
+            Op(OpKind::Pick, Some(value)) => {
+                let depth = usize::try_from(value).map_err(|_| VmError::InvalidDepth(value))?;
+                let word = self.stack.peek_at(depth)?;
+                self.stack.push(word)?;
+            }
+            Op(OpKind::Dropn, Some(value)) => {
+                let count = usize::try_from(value).map_err(|_| VmError::InvalidDepth(value))?;
+                self.stack.drop_n(count)?;
+            }
             Op(OpKind::Halt, None) => self.halted = true,
-            _ => return Err("incorrect op code encountered".to_string()),
+            _ => return Err(VmError::IncorrectOp),
         }
 
         Ok(())
     }
 
-    fn parse_op(&mut self) -> Result<Op, String> {
-        let kind: OpKind = self.program[self.ip].try_into()?;
+    fn binary(
+        &mut self,
+        f: impl FnOnce(Word, Word) -> Result<Word, VmError>,
+    ) -> Result<(), VmError> {
+        let a = self.stack.pop()?;
+        let b = self.stack.pop()?;
+        self.stack.push(f(b, a)?)
+    }
+
+    fn address(&self, value: Word) -> Result<usize, VmError> {
+        let address = usize::try_from(value).map_err(|_| VmError::InvalidAddress(value))?;
+        if address > self.program_size {
+            return Err(VmError::SegmentationFault);
+        }
+        Ok(address)
+    }
+
+    /// The big endian word at `value` in the program image.
+    fn program_word(&self, value: Word) -> Result<Word, VmError> {
+        let address = usize::try_from(value).map_err(|_| VmError::InvalidAddress(value))?;
+        let bytes = self
+            .program()
+            .get(address..address + size_of::<Word>())
+            .ok_or(VmError::SegmentationFault)?;
+        Ok(Word::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// The null-terminated string at `value` in memory, the low byte of each cell a character.
+    fn memory_string(&self, value: Word) -> Result<Vec<u8>, VmError> {
+        let start = self.cell(value)?;
+        let length = self.memory[start..]
+            .iter()
+            .position(|&cell| cell == 0)
+            .ok_or(VmError::MemoryOutOfBounds(self.memory.len() as Word))?;
+        Ok(self.memory[start..start + length]
+            .iter()
+            .map(|&cell| cell as u8)
+            .collect())
+    }
+
+    /// The null-terminated string at `value` in the program image.
+    fn program_string(&self, value: Word) -> Result<Vec<u8>, VmError> {
+        let address = usize::try_from(value).map_err(|_| VmError::InvalidAddress(value))?;
+        let bytes = self
+            .program()
+            .get(address..)
+            .ok_or(VmError::SegmentationFault)?;
+        let length = bytes
+            .iter()
+            .position(|&byte| byte == 0)
+            .ok_or(VmError::SegmentationFault)?;
+        Ok(bytes[..length].to_vec())
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), VmError> {
+        self.io
+            .output()
+            .write_all(bytes)
+            .map_err(|error| VmError::WriteFailed(error.to_string()))
+    }
+
+    fn peek_input(&mut self) -> Result<Option<u8>, VmError> {
+        let buffer = self
+            .io
+            .input()
+            .fill_buf()
+            .map_err(|error| VmError::ReadFailed(error.to_string()))?;
+        Ok(buffer.first().copied())
+    }
+
+    fn next_input(&mut self) -> Result<Option<u8>, VmError> {
+        let byte = self.peek_input()?;
+        if byte.is_some() {
+            self.io.input().consume(1);
+        }
+        Ok(byte)
+    }
+
+    /// Skips whitespace and reads a signed decimal number.
+    fn read_number(&mut self) -> Result<Word, VmError> {
+        while self
+            .peek_input()?
+            .is_some_and(|byte| byte.is_ascii_whitespace())
+        {
+            self.io.input().consume(1);
+        }
+
+        let mut text = String::new();
+        if let Some(sign @ (b'-' | b'+')) = self.peek_input()? {
+            self.io.input().consume(1);
+            text.push(sign as char);
+        }
+        while let Some(digit) = self.peek_input()?.filter(u8::is_ascii_digit) {
+            self.io.input().consume(1);
+            text.push(digit as char);
+        }
+
+        match self.peek_input()? {
+            None if text.is_empty() => Err(VmError::EndOfInput),
+            Some(byte) if text.is_empty() => Err(VmError::InvalidInput((byte as char).to_string())),
+            _ => text.parse().map_err(|_| VmError::InvalidInput(text)),
+        }
+    }
+
+    fn cell(&self, value: Word) -> Result<usize, VmError> {
+        usize::try_from(value)
+            .ok()
+            .filter(|&cell| cell < self.memory.len())
+            .ok_or(VmError::MemoryOutOfBounds(value))
+    }
+
+    fn fault(&self, ip: usize, op: Option<OpKind>, error: VmError) -> VmError {
+        VmError::Fault {
+            ip,
+            op,
+            depth: self.stack.len(),
+            source: Box::new(error),
+        }
+    }
+
+    fn parse_op(&mut self) -> Result<Op, VmError> {
+        let byte = *self
+            .program()
+            .get(self.ip)
+            .ok_or(VmError::SegmentationFault)?;
+        let kind: OpKind = byte.try_into()?;
         self.ip += 1;
 
         if kind.has_operand() {
@@ -130,9 +574,9 @@ impl Machine {
         Ok(Op(kind, None))
     }
 
-    fn extract_word(&mut self) -> Result<Word, String> {
-        if self.ip + 1 > self.program_size {
-            return Err(format!("could not extract word at {}", self.ip));
+    fn extract_word(&mut self) -> Result<Word, VmError> {
+        if self.ip + size_of::<Word>() > self.program_size {
+            return Err(VmError::MissingOperand(self.ip));
         }
         let word = (self.program[self.ip] as Word) << 8 | self.program[self.ip + 1] as Word;
         self.ip += size_of::<Word>();
@@ -143,18 +587,53 @@ impl Machine {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::io::MemoryIo;
+    use crate::object::{Import, Link};
 
     #[test]
     fn test_machine_initialization() {
         let machine = Machine::try_new(&[]).unwrap();
         assert_eq!(machine.program_size, 0);
-        assert_eq!(machine.halted, false);
+        assert!(!machine.halted);
     }
 
     #[test]
     fn test_program_capacity_exceeded() {
         let input = vec![0; PROGRAM_CAPACITY + 1];
         assert!(Machine::try_new(&input).is_err());
+
+        let mut input = vec![OpKind::Pop.into(); PROGRAM_CAPACITY];
+        assert_eq!(
+            Machine::try_new(&input).err(),
+            Some(VmError::ProgramTooLarge(PROGRAM_CAPACITY))
+        );
+        input[PROGRAM_CAPACITY - 1] = OpKind::Halt.into();
+        assert!(Machine::try_new(&input).is_ok());
+    }
+
+    #[test]
+    fn test_from_object() {
+        let mut object = Object::new(vec![OpKind::Load.into(), 0x00, 0x01]);
+        object.data = vec![3, 4];
+        let mut machine = Machine::try_from_object(&object).unwrap();
+        machine.run(false).unwrap();
+        assert!(machine.stack.pop().is_ok_and(|value| value == 4));
+
+        object.data = vec![0; MEMORY_SIZE + 1];
+        assert!(Machine::try_from_object(&object).is_err());
+
+        object.data = Vec::new();
+        object.link = Some(Link {
+            imports: vec![Import {
+                offset: 1,
+                name: "print".to_string(),
+            }],
+            ..Link::default()
+        });
+        assert_eq!(
+            Machine::try_from_object(&object).err(),
+            Some(VmError::UndefinedSymbol("print".to_string()))
+        );
     }
 
     #[test]
@@ -229,6 +708,156 @@ mod tests {
         assert!(machine.stack.pop().is_ok_and(|value| value == 5));
     }
 
+    #[test]
+    fn test_fault_context() {
+        let mut machine =
+            Machine::try_new(&[OpKind::Push.into(), 0x00, 0x01, OpKind::Add.into()]).unwrap();
+        let error = machine.run(false).unwrap_err();
+        assert_eq!(
+            error,
+            VmError::Fault {
+                ip: 3,
+                op: Some(OpKind::Add),
+                depth: 0,
+                source: Box::new(VmError::StackUnderflow),
+            }
+        );
+        assert_eq!(error.kind(), &VmError::StackUnderflow);
+        assert_eq!(error.to_string(), "stack underflow");
+    }
+
+    #[test]
+    fn test_modulo() {
+        let mut machine = Machine::try_new(&[
+            OpKind::Push.into(),
+            0x00,
+            0x11,
+            OpKind::Push.into(),
+            0x00,
+            0x05,
+            OpKind::Mod.into(),
+        ])
+        .unwrap();
+        machine.run(false).unwrap();
+        assert!(machine.stack.pop().is_ok_and(|value| value == 2));
+    }
+
+    #[test]
+    fn test_arithmetic_wraps() {
+        for (kind, b, a, expected) in [
+            (OpKind::Add, Word::MAX, 1 as Word, Word::MIN),
+            (OpKind::Sub, Word::MIN, 1, Word::MAX),
+            (OpKind::Mul, Word::MAX, 2, -2),
+            (OpKind::Div, Word::MIN, -1, Word::MIN),
+            (OpKind::Mod, Word::MIN, -1, 0),
+        ] {
+            let mut program = vec![OpKind::Push.into()];
+            program.extend(b.to_be_bytes());
+            program.push(OpKind::Push.into());
+            program.extend(a.to_be_bytes());
+            program.push(kind.into());
+
+            let mut machine = Machine::try_new(&program).unwrap();
+            machine.run(false).unwrap();
+            assert!(machine.stack.pop().is_ok_and(|value| value == expected));
+        }
+    }
+
+    #[test]
+    fn test_comparisons() {
+        for (kind, expected) in [
+            (OpKind::Eq, 0),
+            (OpKind::Ne, 1),
+            (OpKind::Lt, 1),
+            (OpKind::Le, 1),
+            (OpKind::Gt, 0),
+            (OpKind::Ge, 0),
+        ] {
+            let mut machine = Machine::try_new(&[
+                OpKind::Push.into(),
+                0x00,
+                0x02,
+                OpKind::Push.into(),
+                0x00,
+                0x03,
+                kind.into(),
+            ])
+            .unwrap();
+            machine.run(false).unwrap();
+            assert!(machine.stack.pop().is_ok_and(|value| value == expected));
+        }
+    }
+
+    #[test]
+    fn test_bitwise_operations() {
+        for (kind, expected) in [
+            (OpKind::And, 0b0100),
+            (OpKind::Or, 0b1101),
+            (OpKind::Xor, 0b1001),
+            (OpKind::Shl, 0b1100 << 0b0101),
+            (OpKind::Shr, 0),
+        ] {
+            let mut machine = Machine::try_new(&[
+                OpKind::Push.into(),
+                0x00,
+                0b1100,
+                OpKind::Push.into(),
+                0x00,
+                0b0101,
+                kind.into(),
+            ])
+            .unwrap();
+            machine.run(false).unwrap();
+            assert!(machine.stack.pop().is_ok_and(|value| value == expected));
+        }
+
+        let mut machine =
+            Machine::try_new(&[OpKind::Push.into(), 0x00, 0x00, OpKind::Not.into()]).unwrap();
+        machine.run(false).unwrap();
+        assert!(machine.stack.pop().is_ok_and(|value| value == -1));
+    }
+
+    #[test]
+    fn test_invalid_shift() {
+        let mut machine = Machine::try_new(&[
+            OpKind::Push.into(),
+            0x00,
+            0x01,
+            OpKind::Push.into(),
+            0x00,
+            0x10,
+            OpKind::Shl.into(),
+        ])
+        .unwrap();
+        assert_eq!(
+            machine.run(false).unwrap_err().kind(),
+            &VmError::InvalidShift(16)
+        );
+    }
+
+    #[test]
+    fn test_goifz() {
+        let mut machine = Machine::try_new(&[
+            OpKind::Push.into(),
+            0x00,
+            0x00,
+            OpKind::Goifz.into(),
+            0x00,
+            0x0a,
+            OpKind::Push.into(),
+            0x00,
+            0x01,
+            OpKind::Halt.into(),
+            OpKind::Push.into(),
+            0x00,
+            0x02,
+        ])
+        .unwrap();
+        machine.run(false).unwrap();
+        assert!(machine.stack.pop().is_ok_and(|value| value == 2));
+        assert!(machine.stack.pop().is_err());
+    }
+
     #[test]
     fn test_division_by_zero() {
         let mut machine = Machine::try_new(&[
@@ -241,13 +870,487 @@ mod tests {
             OpKind::Div.into(),
         ])
         .unwrap();
-        assert!(machine.run(false).is_err());
+        assert_eq!(
+            machine.run(false).unwrap_err().kind(),
+            &VmError::DivisionByZero
+        );
     }
 
     #[test]
     fn test_unknown_opcode() {
         let mut machine = Machine::try_new(&[0xFF]).unwrap();
-        assert!(machine.run(false).is_err());
+        assert_eq!(
+            machine.run(false).unwrap_err().kind(),
+            &VmError::UnknownOpKind(0xFF)
+        );
+    }
+
+    #[test]
+    fn test_call_and_return() {
+        let mut machine = Machine::try_new(&[
+            OpKind::Call.into(),
+            0x00,
+            0x04,
+            OpKind::Halt.into(),
+            OpKind::Push.into(),
+            0x00,
+            0x07,
+            OpKind::Ret.into(),
+        ])
+        .unwrap();
+        machine.run(false).unwrap();
+        assert!(machine.stack.pop().is_ok_and(|value| value == 7));
+        assert!(machine.returns.is_empty());
+    }
+
+    #[test]
+    fn test_return_stack_underflow() {
+        let mut machine = Machine::try_new(&[OpKind::Ret.into()]).unwrap();
+        assert_eq!(
+            machine.run(false).unwrap_err().kind(),
+            &VmError::ReturnStackUnderflow
+        );
+    }
+
+    #[test]
+    fn test_return_stack_overflow() {
+        let mut machine = Machine::try_new(&[OpKind::Call.into(), 0x00, 0x00]).unwrap();
+        assert_eq!(
+            machine.run(false).unwrap_err().kind(),
+            &VmError::ReturnStackOverflow
+        );
+    }
+
+    #[test]
+    fn test_load_and_store() {
+        let mut machine = Machine::try_new(&[
+            OpKind::Push.into(),
+            0x00,
+            0x2a,
+            OpKind::Store.into(),
+            0x00,
+            0x03,
+            OpKind::Load.into(),
+            0x00,
+            0x03,
+        ])
+        .unwrap();
+        machine.run(false).unwrap();
+        assert_eq!(machine.memory[3], 42);
+        assert!(machine.stack.pop().is_ok_and(|value| value == 42));
+    }
+
+    #[test]
+    fn test_indirect_load_and_store() {
+        let mut machine = Machine::try_new(&[
+            OpKind::Push.into(),
+            0x00,
+            0x07,
+            OpKind::Push.into(),
+            0x00,
+            0x05,
+            OpKind::Storei.into(),
+            OpKind::Push.into(),
+            0x00,
+            0x05,
+            OpKind::Loadi.into(),
+        ])
+        .unwrap();
+        machine.run(false).unwrap();
+        assert_eq!(machine.memory[5], 7);
+        assert!(machine.stack.pop().is_ok_and(|value| value == 7));
+    }
+
+    #[test]
+    fn test_load_from_program() {
+        let mut machine = Machine::try_new(&[
+            OpKind::Push.into(),
+            0x00,
+            0x05,
+            OpKind::Loadp.into(),
+            OpKind::Halt.into(),
+            0x12,
+            0x34,
+        ])
+        .unwrap();
+        machine.run(false).unwrap();
+        assert!(machine.stack.pop().is_ok_and(|value| value == 0x1234));
+
+        let mut machine =
+            Machine::try_new(&[OpKind::Push.into(), 0x00, 0x04, OpKind::Loadp.into()]).unwrap();
+        assert_eq!(
+            machine.run(false).unwrap_err().kind(),
+            &VmError::SegmentationFault
+        );
+
+        let mut machine =
+            Machine::try_new(&[OpKind::Push.into(), 0xff, 0xff, OpKind::Loadp.into()]).unwrap();
+        assert_eq!(
+            machine.run(false).unwrap_err().kind(),
+            &VmError::InvalidAddress(-1)
+        );
+    }
+
+    #[test]
+    fn test_output() {
+        let mut machine = Machine::try_new(&[
+            OpKind::Push.into(),
+            0x00,
+            0x41,
+            OpKind::Putc.into(),
+            OpKind::Push.into(),
+            0xff,
+            0xf4,
+            OpKind::Putn.into(),
+            OpKind::Push.into(),
+            0x00,
+            0x0d,
+            OpKind::Printp.into(),
+            OpKind::Halt.into(),
+            0x0a,
+            0x00,
+        ])
+        .unwrap()
+        .with_io(MemoryIo::default());
+        machine.run(false).unwrap();
+        assert!(machine.stack.is_empty());
+        assert_eq!(machine.io().output, b"A-12\n");
+
+        let mut machine = Machine::try_new(&[OpKind::Push.into(), 0x00, 0x07, OpKind::Echo.into()])
+            .unwrap()
+            .with_io(MemoryIo::default());
+        machine.run(true).unwrap();
+        let output = String::from_utf8(machine.io().output.clone()).unwrap();
+        assert!(output.starts_with("[DEBUG] 003 | Op(Push, Some(7))"));
+        assert!(output.contains("\n7\n[DEBUG]"));
+
+        /* Without the null terminator the string runs off the program */
+        let mut machine =
+            Machine::try_new(&[OpKind::Push.into(), 0x00, 0x03, OpKind::Printp.into()]).unwrap();
+        assert_eq!(
+            machine.run(false).unwrap_err().kind(),
+            &VmError::SegmentationFault
+        );
+
+        let mut machine = Machine::try_with_memory(
+            &[
+                OpKind::Push.into(),
+                0x00,
+                0x0a,
+                OpKind::Store.into(),
+                0x00,
+                0x00,
+                OpKind::Push.into(),
+                0x00,
+                0x00,
+                OpKind::Prints.into(),
+            ],
+            1,
+        )
+        .unwrap();
+        assert_eq!(
+            machine.run(false).unwrap_err().kind(),
+            &VmError::MemoryOutOfBounds(1)
+        );
+    }
+
+    #[test]
+    fn test_getc() {
+        let mut machine = Machine::try_new(&[
+            OpKind::Getc.into(),
+            OpKind::Getc.into(),
+            OpKind::Getc.into(),
+        ])
+        .unwrap()
+        .with_io(MemoryIo::new("a\n"));
+        machine.run(false).unwrap();
+        assert!(machine.stack.pop().is_ok_and(|value| value == -1));
+        assert!(machine.stack.pop().is_ok_and(|value| value == '\n' as Word));
+        assert!(machine.stack.pop().is_ok_and(|value| value == 'a' as Word));
+    }
+
+    #[test]
+    fn test_readn() {
+        let program = [OpKind::Readn.into(), OpKind::Readn.into()];
+        let mut machine = Machine::try_new(&program)
+            .unwrap()
+            .with_io(MemoryIo::new("  12\n-7 "));
+        machine.run(false).unwrap();
+        assert!(machine.stack.pop().is_ok_and(|value| value == -7));
+        assert!(machine.stack.pop().is_ok_and(|value| value == 12));
+
+        let mut machine = Machine::try_new(&program)
+            .unwrap()
+            .with_io(MemoryIo::new("1 "));
+        assert_eq!(machine.run(false).unwrap_err().kind(), &VmError::EndOfInput);
+
+        let mut machine = Machine::try_new(&program)
+            .unwrap()
+            .with_io(MemoryIo::new("1 x"));
+        assert_eq!(
+            machine.run(false).unwrap_err().kind(),
+            &VmError::InvalidInput("x".to_string())
+        );
+
+        let mut machine = Machine::try_new(&program)
+            .unwrap()
+            .with_io(MemoryIo::new("32768"));
+        assert_eq!(
+            machine.run(false).unwrap_err().kind(),
+            &VmError::InvalidInput("32768".to_string())
+        );
+    }
+
+    #[test]
+    fn test_syscall() {
+        let program = [
+            OpKind::Push.into(),
+            0x00,
+            0x02,
+            OpKind::Push.into(),
+            0x00,
+            0x03,
+            OpKind::Syscall.into(),
+            0x00,
+            0x01,
+        ];
+        let mut machine = Machine::try_with_memory(&program, 1).unwrap();
+        machine.register_syscall(1, |ctx| {
+            let (a, b) = (ctx.pop()?, ctx.pop()?);
+            ctx.store(0, a * b)?;
+            ctx.push(a + b)
+        });
+        machine.run(false).unwrap();
+        assert!(machine.stack.pop().is_ok_and(|value| value == 5));
+        assert_eq!(machine.memory, vec![6]);
+
+        let mut machine = Machine::try_new(&program).unwrap();
+        assert_eq!(
+            machine.run(false).unwrap_err().kind(),
+            &VmError::UnknownSyscall(1)
+        );
+
+        let mut machine = Machine::try_new(&program).unwrap();
+        machine.register_syscall(1, |_| Err(VmError::SyscallFailed("denied".to_string())));
+        let error = machine.run(false).unwrap_err();
+        assert!(matches!(
+            error,
+            VmError::Fault {
+                ip: 6,
+                op: Some(OpKind::Syscall),
+                ..
+            }
+        ));
+        assert_eq!(error.to_string(), "syscall failed: denied");
+    }
+
+    #[test]
+    fn test_run_for() {
+        let mut machine = Machine::try_new(&[OpKind::Goto.into(), 0x00, 0x00]).unwrap();
+        assert_eq!(machine.run_for(100), RunOutcome::OutOfFuel);
+        assert_eq!(machine.ip(), 0);
+
+        let mut machine = Machine::try_new(&[
+            OpKind::Push.into(),
+            0x00,
+            0x01,
+            OpKind::Push.into(),
+            0x00,
+            0x02,
+            OpKind::Add.into(),
+        ])
+        .unwrap();
+        assert_eq!(machine.run_for(2), RunOutcome::OutOfFuel);
+        assert_eq!(machine.stack.len(), 2);
+        assert_eq!(machine.run_for(100), RunOutcome::Halted);
+        assert!(machine.stack.pop().is_ok_and(|value| value == 3));
+        assert_eq!(machine.run_for(100), RunOutcome::Halted);
+
+        let mut machine = Machine::try_new(&[OpKind::Pop.into()]).unwrap();
+        let RunOutcome::Trapped(error) = machine.run_for(100) else {
+            panic!("expected a trap");
+        };
+        assert_eq!(error.kind(), &VmError::StackUnderflow);
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut machine = Machine::try_new(&[OpKind::Goto.into(), 0x00, 0x00]).unwrap();
+        let token = machine.cancel_token();
+        let handle = std::thread::spawn(move || {
+            let result = machine.run(false);
+            (machine, result)
+        });
+        token.cancel();
+
+        let (mut machine, result) = handle.join().unwrap();
+        assert_eq!(result, Err(VmError::Interrupted));
+        assert_eq!(machine.ip(), 0);
+        assert!(!machine.halted());
+        assert_eq!(
+            machine.run_for(100),
+            RunOutcome::Trapped(VmError::Interrupted)
+        );
+
+        token.reset();
+        assert_eq!(machine.run_for(100), RunOutcome::OutOfFuel);
+    }
+
+    #[test]
+    fn test_deadline() {
+        let mut machine = Machine::try_new(&[OpKind::Goto.into(), 0x00, 0x00]).unwrap();
+        machine.set_deadline(Some(Instant::now() + std::time::Duration::from_millis(10)));
+        assert_eq!(machine.run(false), Err(VmError::DeadlineExceeded));
+
+        machine.set_deadline(None);
+        assert_eq!(machine.run_for(100), RunOutcome::OutOfFuel);
+    }
+
+    #[test]
+    fn test_snapshot_and_restore() {
+        let mut machine = Machine::try_with_memory(
+            &[
+                OpKind::Push.into(),
+                0x00,
+                0x05,
+                OpKind::Call.into(),
+                0x00,
+                0x07,
+                OpKind::Halt.into(),
+                OpKind::Copy.into(),
+                OpKind::Store.into(),
+                0x00,
+                0x01,
+                OpKind::Ret.into(),
+            ],
+            2,
+        )
+        .unwrap();
+        assert_eq!(machine.run_for(3), RunOutcome::OutOfFuel);
+
+        let snapshot = machine.snapshot();
+        assert_eq!(snapshot.ip, 8);
+        assert_eq!(snapshot.stack, vec![5, 5]);
+        assert_eq!(snapshot.returns, vec![6]);
+
+        let bytes: Vec<u8> = (&snapshot).into();
+        let mut restored =
+            Machine::try_from_snapshot(&Snapshot::try_from(&bytes[..]).unwrap()).unwrap();
+        assert_eq!(restored.run_for(100), RunOutcome::Halted);
+        assert_eq!(machine.run_for(100), RunOutcome::Halted);
+        assert_eq!(restored.snapshot(), machine.snapshot());
+        assert_eq!(restored.memory(), &[0, 5]);
+
+        for (corrupt, error) in [
+            (
+                Snapshot {
+                    ip: 100,
+                    ..snapshot.clone()
+                },
+                VmError::MalformedSnapshot,
+            ),
+            (
+                Snapshot {
+                    stack: vec![0; 1 << 11],
+                    ..snapshot.clone()
+                },
+                VmError::MalformedSnapshot,
+            ),
+            (
+                Snapshot {
+                    returns: vec![0; 1 << 11],
+                    ..snapshot
+                },
+                VmError::MalformedSnapshot,
+            ),
+            (
+                Snapshot {
+                    program: vec![OpKind::Halt.into(); PROGRAM_CAPACITY],
+                    ip: PROGRAM_CAPACITY,
+                    ..Snapshot::default()
+                },
+                VmError::SegmentationFault,
+            ),
+        ] {
+            let result =
+                Machine::try_from_snapshot(&corrupt).and_then(|mut machine| machine.step());
+            assert_eq!(result.unwrap_err().kind(), &error);
+        }
+    }
+
+    #[test]
+    fn test_memory_out_of_bounds() {
+        let mut machine = Machine::try_with_memory(&[OpKind::Load.into(), 0x00, 0x04], 4).unwrap();
+        assert_eq!(
+            machine.run(false).unwrap_err().kind(),
+            &VmError::MemoryOutOfBounds(4)
+        );
+
+        let mut machine =
+            Machine::try_new(&[OpKind::Push.into(), 0xff, 0xff, OpKind::Loadi.into()]).unwrap();
+        assert_eq!(
+            machine.run(false).unwrap_err().kind(),
+            &VmError::MemoryOutOfBounds(-1)
+        );
+    }
+
+    #[test]
+    fn test_stack_manipulation() {
+        let mut machine = Machine::try_new(&[
+            OpKind::Push.into(),
+            0x00,
+            0x01,
+            OpKind::Push.into(),
+            0x00,
+            0x02,
+            OpKind::Over.into(),
+            OpKind::Swap.into(),
+            OpKind::Rot.into(),
+            OpKind::Pick.into(),
+            0x00,
+            0x02,
+            OpKind::Dropn.into(),
+            0x00,
+            0x01,
+        ])
+        .unwrap();
+        machine.run(false).unwrap();
+        assert_eq!(format!("{}", machine.stack), "1 -> 2 -> 1 -> None");
+    }
+
+    #[test]
+    fn test_invalid_depth() {
+        let mut machine = Machine::try_new(&[OpKind::Pick.into(), 0xff, 0xff]).unwrap();
+        assert_eq!(
+            machine.run(false).unwrap_err().kind(),
+            &VmError::InvalidDepth(-1)
+        );
+    }
+
+    #[test]
+    fn test_single_step() {
+        let mut machine = Machine::try_new(&[
+            OpKind::Push.into(),
+            0x00,
+            0x01,
+            OpKind::Call.into(),
+            0x00,
+            0x07,
+            OpKind::Halt.into(),
+            OpKind::Ret.into(),
+        ])
+        .unwrap();
+        machine.step().unwrap();
+        assert_eq!(machine.ip(), 3);
+        assert!(machine.stack().peek_at(0).is_ok_and(|value| value == 1));
+        machine.step().unwrap();
+        assert_eq!(machine.ip(), 7);
+        assert_eq!(machine.call_depth(), 1);
+        machine.step().unwrap();
+        assert_eq!(machine.ip(), 6);
+        assert!(!machine.halted());
+        machine.step().unwrap();
+        assert!(machine.halted());
     }
 
     #[test]
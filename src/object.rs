@@ -0,0 +1,443 @@
+use std::collections::BTreeMap;
+use std::mem::size_of;
+
+use crate::error::VmError;
+use crate::op::Word;
+
+pub const MAGIC: [u8; 4] = *b"VMRS";
+pub const VERSION: u16 = 3;
+
+const SYMBOLS: u16 = 1 << 0;
+const LINES: u16 = 1 << 1;
+const LINK: u16 = 1 << 2;
+const DATA: u16 = 1 << 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub offset: usize,
+    pub row: usize,
+    pub col: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Segment {
+    Code,
+    Memory,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataKind {
+    Byte,
+    Word,
+}
+
+/* Bytes in the code section placed by a data directive rather than an instruction */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataRange {
+    pub offset: usize,
+    pub length: usize,
+    pub kind: DataKind,
+}
+
+/* An operand holding an address relative to the start of its object's segment */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    pub offset: usize,
+    pub segment: Segment,
+}
+
+/* An operand to which the address of a symbol from another object is added */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+    pub offset: usize,
+    pub name: String,
+}
+
+/* Everything `link` needs to place an object next to others */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Link {
+    pub cells: Word,
+    pub relocations: Vec<Relocation>,
+    pub imports: Vec<Import>,
+    pub exports: BTreeMap<String, Word>,
+}
+
+/*
+ * Layout (all integers are big endian):
+ *
+ *   magic    [u8; 4]     "VMRS"
+ *   version  u16
+ *   flags    u16         which optional sections follow
+ *   code     u32 length, then the bytecode
+ *   data     u32 length, then the initial memory words
+ *   symbols  u32 count, then (u16 length, name, address word) per symbol
+ *   lines    u32 count, then (u32 offset, u32 row, u32 col) per entry
+ *   link     cells word, then
+ *            u32 count, then (u8 segment, u32 offset) per relocation
+ *            u32 count, then (u16 length, name, u32 offset) per import
+ *            u32 count, then (u16 length, name, address word) per export
+ *   data     u32 count, then (u8 kind, u32 offset, u32 length) per range of data in
+ *            the code, kind 0 for bytes and 1 for words
+ *
+ * Version 1 files are the same without the link section, version 2 without the data
+ * ranges.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Object {
+    pub code: Vec<u8>,
+    pub data: Vec<Word>,
+    pub symbols: Option<BTreeMap<String, Word>>,
+    pub lines: Option<Vec<Line>>,
+    pub link: Option<Link>,
+    pub data_ranges: Vec<DataRange>,
+}
+
+impl Object {
+    pub fn new(code: Vec<u8>) -> Self {
+        Self {
+            code,
+            ..Self::default()
+        }
+    }
+
+    pub fn line_at(&self, offset: usize) -> Option<&Line> {
+        self.lines
+            .as_ref()?
+            .iter()
+            .find(|line| line.offset == offset)
+    }
+}
+
+impl From<&Object> for Vec<u8> {
+    fn from(object: &Object) -> Vec<u8> {
+        let mut flags = 0;
+        if object.symbols.is_some() {
+            flags |= SYMBOLS;
+        }
+        if object.lines.is_some() {
+            flags |= LINES;
+        }
+        if object.link.is_some() {
+            flags |= LINK;
+        }
+        if !object.data_ranges.is_empty() {
+            flags |= DATA;
+        }
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_be_bytes());
+        bytes.extend(flags.to_be_bytes());
+
+        bytes.extend((object.code.len() as u32).to_be_bytes());
+        bytes.extend(&object.code);
+
+        bytes.extend((object.data.len() as u32).to_be_bytes());
+        for word in &object.data {
+            bytes.extend(word.to_be_bytes());
+        }
+
+        if let Some(symbols) = &object.symbols {
+            write_symbols(&mut bytes, symbols);
+        }
+
+        if let Some(lines) = &object.lines {
+            bytes.extend((lines.len() as u32).to_be_bytes());
+            for line in lines {
+                bytes.extend((line.offset as u32).to_be_bytes());
+                bytes.extend((line.row as u32).to_be_bytes());
+                bytes.extend((line.col as u32).to_be_bytes());
+            }
+        }
+
+        if let Some(link) = &object.link {
+            bytes.extend(link.cells.to_be_bytes());
+
+            bytes.extend((link.relocations.len() as u32).to_be_bytes());
+            for relocation in &link.relocations {
+                bytes.push(match relocation.segment {
+                    Segment::Code => 0,
+                    Segment::Memory => 1,
+                });
+                bytes.extend((relocation.offset as u32).to_be_bytes());
+            }
+
+            bytes.extend((link.imports.len() as u32).to_be_bytes());
+            for import in &link.imports {
+                write_name(&mut bytes, &import.name);
+                bytes.extend((import.offset as u32).to_be_bytes());
+            }
+
+            write_symbols(&mut bytes, &link.exports);
+        }
+
+        if !object.data_ranges.is_empty() {
+            bytes.extend((object.data_ranges.len() as u32).to_be_bytes());
+            for range in &object.data_ranges {
+                bytes.push(match range.kind {
+                    DataKind::Byte => 0,
+                    DataKind::Word => 1,
+                });
+                bytes.extend((range.offset as u32).to_be_bytes());
+                bytes.extend((range.length as u32).to_be_bytes());
+            }
+        }
+
+        bytes
+    }
+}
+
+fn write_name(bytes: &mut Vec<u8>, name: &str) {
+    bytes.extend((name.len() as u16).to_be_bytes());
+    bytes.extend(name.as_bytes());
+}
+
+fn write_symbols(bytes: &mut Vec<u8>, symbols: &BTreeMap<String, Word>) {
+    bytes.extend((symbols.len() as u32).to_be_bytes());
+    for (name, address) in symbols {
+        write_name(bytes, name);
+        bytes.extend(address.to_be_bytes());
+    }
+}
+
+impl TryFrom<&[u8]> for Object {
+    type Error = VmError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        let mut reader = Reader::new(bytes);
+
+        if reader.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err(VmError::InvalidMagic);
+        }
+
+        let version = reader.u16()?;
+        if version == 0 || version > VERSION {
+            return Err(VmError::UnsupportedVersion(version));
+        }
+        let flags = reader.u16()?;
+        let defined = match version {
+            1 => SYMBOLS | LINES,
+            2 => SYMBOLS | LINES | LINK,
+            _ => SYMBOLS | LINES | LINK | DATA,
+        };
+        if flags & !defined != 0 {
+            return Err(VmError::MalformedObject);
+        }
+
+        let length = reader.u32()? as usize;
+        let code = reader.take(length)?.to_vec();
+
+        let length = reader.u32()? as usize;
+        let mut data = Vec::new();
+        for _ in 0..length {
+            data.push(reader.u16()? as Word);
+        }
+
+        let mut object = Object {
+            code,
+            data,
+            symbols: None,
+            lines: None,
+            link: None,
+            data_ranges: Vec::new(),
+        };
+
+        if flags & SYMBOLS != 0 {
+            object.symbols = Some(reader.symbols()?);
+        }
+
+        if flags & LINES != 0 {
+            let mut lines = Vec::new();
+            for _ in 0..reader.u32()? {
+                lines.push(Line {
+                    offset: reader.u32()? as usize,
+                    row: reader.u32()? as usize,
+                    col: reader.u32()? as usize,
+                });
+            }
+            object.lines = Some(lines);
+        }
+
+        if flags & LINK != 0 {
+            let mut link = Link {
+                cells: reader.u16()? as Word,
+                ..Link::default()
+            };
+            for _ in 0..reader.u32()? {
+                let segment = match reader.take(1)?[0] {
+                    0 => Segment::Code,
+                    1 => Segment::Memory,
+                    _ => return Err(VmError::MalformedObject),
+                };
+                link.relocations.push(Relocation {
+                    offset: reader.u32()? as usize,
+                    segment,
+                });
+            }
+            for _ in 0..reader.u32()? {
+                link.imports.push(Import {
+                    name: reader.name()?,
+                    offset: reader.u32()? as usize,
+                });
+            }
+            link.exports = reader.symbols()?;
+            object.link = Some(link);
+        }
+
+        if flags & DATA != 0 {
+            for _ in 0..reader.u32()? {
+                let kind = match reader.take(1)?[0] {
+                    0 => DataKind::Byte,
+                    1 => DataKind::Word,
+                    _ => return Err(VmError::MalformedObject),
+                };
+                object.data_ranges.push(DataRange {
+                    offset: reader.u32()? as usize,
+                    length: reader.u32()? as usize,
+                    kind,
+                });
+            }
+        }
+
+        if !reader.is_empty() {
+            return Err(VmError::MalformedObject);
+        }
+
+        Ok(object)
+    }
+}
+
+/* A cursor over big endian binary data, failing with `MalformedObject` when it runs out */
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, offset: 0 }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.offset == self.bytes.len()
+    }
+
+    pub(crate) fn take(&mut self, length: usize) -> Result<&'a [u8], VmError> {
+        let slice = self
+            .bytes
+            .get(self.offset..self.offset + length)
+            .ok_or(VmError::MalformedObject)?;
+        self.offset += length;
+        Ok(slice)
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, VmError> {
+        let bytes = self.take(size_of::<u16>())?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, VmError> {
+        let bytes = self.take(size_of::<u32>())?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn name(&mut self) -> Result<String, VmError> {
+        let length = self.u16()? as usize;
+        String::from_utf8(self.take(length)?.to_vec()).map_err(|_| VmError::MalformedObject)
+    }
+
+    fn symbols(&mut self) -> Result<BTreeMap<String, Word>, VmError> {
+        let mut symbols = BTreeMap::new();
+        for _ in 0..self.u32()? {
+            let name = self.name()?;
+            symbols.insert(name, self.u16()? as Word);
+        }
+        Ok(symbols)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let object = Object {
+            code: vec![0x00, 0x00, 0x01, 0x02],
+            data: vec![1, -1],
+            symbols: Some(BTreeMap::from([("main".to_string(), 0)])),
+            lines: Some(vec![Line {
+                offset: 3,
+                row: 2,
+                col: 1,
+            }]),
+            link: Some(Link {
+                cells: 2,
+                relocations: vec![Relocation {
+                    offset: 1,
+                    segment: Segment::Memory,
+                }],
+                imports: vec![Import {
+                    offset: 1,
+                    name: "print".to_string(),
+                }],
+                exports: BTreeMap::from([("main".to_string(), 0)]),
+            }),
+            data_ranges: vec![DataRange {
+                offset: 3,
+                length: 1,
+                kind: DataKind::Byte,
+            }],
+        };
+        let mut bytes: Vec<u8> = (&object).into();
+        assert_eq!(Object::try_from(&bytes[..]).unwrap(), object);
+
+        /* Version 1 had no link section and version 2 no data ranges */
+        for version in [1u16, 2] {
+            bytes[4..6].copy_from_slice(&version.to_be_bytes());
+            assert_eq!(Object::try_from(&bytes[..]), Err(VmError::MalformedObject));
+        }
+    }
+
+    #[test]
+    fn test_optional_sections() {
+        let object = Object::new(vec![0x0a]);
+        let bytes: Vec<u8> = (&object).into();
+        assert_eq!(bytes.len(), 4 + 2 + 2 + 4 + 1 + 4);
+        assert_eq!(Object::try_from(&bytes[..]).unwrap(), object);
+
+        let mut bytes = bytes;
+        bytes[6..8].copy_from_slice(&(1u16 << 15).to_be_bytes());
+        assert_eq!(Object::try_from(&bytes[..]), Err(VmError::MalformedObject));
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        assert_eq!(Object::try_from(&[0x00][..]), Err(VmError::InvalidMagic));
+
+        let mut bytes: Vec<u8> = (&Object::new(vec![])).into();
+        bytes[5] = 0xff;
+        assert_eq!(
+            Object::try_from(&bytes[..]),
+            Err(VmError::UnsupportedVersion(0x00ff))
+        );
+    }
+
+    #[test]
+    fn test_version_one() {
+        let mut bytes: Vec<u8> = (&Object::new(vec![0x0a])).into();
+        bytes[5] = 0x01;
+        assert_eq!(
+            Object::try_from(&bytes[..]).unwrap(),
+            Object::new(vec![0x0a])
+        );
+    }
+
+    #[test]
+    fn test_truncated_object() {
+        let bytes: Vec<u8> = (&Object::new(vec![0x00, 0x00, 0x01])).into();
+        assert_eq!(
+            Object::try_from(&bytes[..bytes.len() - 1]),
+            Err(VmError::MalformedObject)
+        );
+    }
+}
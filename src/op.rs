@@ -1,21 +1,70 @@
+use std::fmt;
+
+use crate::error::VmError;
+
 pub type Word = i16;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum OpKind {
     /* Basic Stack Operations */
     Push,
     Pop,
     Echo,
+    Swap,
+    Over,
+    Rot,
+    Pick,
+    Dropn,
 
     /* Arithematic */
     Add,
     Sub,
     Mul,
     Div,
+    Mod,
+
+    /* Comparison */
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+
+    /* Logic */
+    And,
+    Or,
+    Xor,
+    Not,
+    Shl,
+    Shr,
 
     /* Navigation */
     Goto,
     Goif,
+    Goifz,
+    Call,
+    Ret,
+
+    /* Memory */
+    Load,
+    Store,
+    Loadi,
+    Storei,
+    Loadp,
+
+    /* Output */
+    Putc,
+    Putn,
+    Prints,
+    Printp,
+
+    /* Input */
+    Getc,
+    Readn,
+
+    /* Host */
+    Syscall,
 
     /* Other */
     Copy,
@@ -23,7 +72,7 @@ pub enum OpKind {
 }
 
 impl TryFrom<u8> for OpKind {
-    type Error = String;
+    type Error = VmError;
     fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
             0x00 => Ok(OpKind::Push),
@@ -37,13 +86,46 @@ impl TryFrom<u8> for OpKind {
             0x08 => Ok(OpKind::Goif),
             0x09 => Ok(OpKind::Copy),
             0x0a => Ok(OpKind::Halt),
-            _ => Err(format!("unknown binary op kind: '{}'", value)),
+            0x0b => Ok(OpKind::Call),
+            0x0c => Ok(OpKind::Ret),
+            0x0d => Ok(OpKind::Load),
+            0x0e => Ok(OpKind::Store),
+            0x0f => Ok(OpKind::Loadi),
+            0x10 => Ok(OpKind::Storei),
+            0x11 => Ok(OpKind::Eq),
+            0x12 => Ok(OpKind::Ne),
+            0x13 => Ok(OpKind::Lt),
+            0x14 => Ok(OpKind::Le),
+            0x15 => Ok(OpKind::Gt),
+            0x16 => Ok(OpKind::Ge),
+            0x17 => Ok(OpKind::And),
+            0x18 => Ok(OpKind::Or),
+            0x19 => Ok(OpKind::Xor),
+            0x1a => Ok(OpKind::Not),
+            0x1b => Ok(OpKind::Shl),
+            0x1c => Ok(OpKind::Shr),
+            0x1d => Ok(OpKind::Mod),
+            0x1e => Ok(OpKind::Goifz),
+            0x1f => Ok(OpKind::Swap),
+            0x20 => Ok(OpKind::Over),
+            0x21 => Ok(OpKind::Rot),
+            0x22 => Ok(OpKind::Pick),
+            0x23 => Ok(OpKind::Dropn),
+            0x24 => Ok(OpKind::Loadp),
+            0x25 => Ok(OpKind::Putc),
+            0x26 => Ok(OpKind::Putn),
+            0x27 => Ok(OpKind::Prints),
+            0x28 => Ok(OpKind::Printp),
+            0x29 => Ok(OpKind::Getc),
+            0x2a => Ok(OpKind::Readn),
+            0x2b => Ok(OpKind::Syscall),
+            _ => Err(VmError::UnknownOpKind(value)),
         }
     }
 }
 
 impl TryFrom<String> for OpKind {
-    type Error = String;
+    type Error = VmError;
 
     fn try_from(value: String) -> Result<Self, Self::Error> {
         match value.as_str() {
@@ -58,15 +140,48 @@ impl TryFrom<String> for OpKind {
             "GOIF" => Ok(OpKind::Goif),
             "COPY" => Ok(OpKind::Copy),
             "HALT" => Ok(OpKind::Halt),
+            "CALL" => Ok(OpKind::Call),
+            "RET" => Ok(OpKind::Ret),
+            "LOAD" => Ok(OpKind::Load),
+            "STORE" => Ok(OpKind::Store),
+            "LOADI" => Ok(OpKind::Loadi),
+            "STOREI" => Ok(OpKind::Storei),
+            "EQ" => Ok(OpKind::Eq),
+            "NE" => Ok(OpKind::Ne),
+            "LT" => Ok(OpKind::Lt),
+            "LE" => Ok(OpKind::Le),
+            "GT" => Ok(OpKind::Gt),
+            "GE" => Ok(OpKind::Ge),
+            "AND" => Ok(OpKind::And),
+            "OR" => Ok(OpKind::Or),
+            "XOR" => Ok(OpKind::Xor),
+            "NOT" => Ok(OpKind::Not),
+            "SHL" => Ok(OpKind::Shl),
+            "SHR" => Ok(OpKind::Shr),
+            "MOD" => Ok(OpKind::Mod),
+            "GOIFZ" => Ok(OpKind::Goifz),
+            "SWAP" => Ok(OpKind::Swap),
+            "OVER" => Ok(OpKind::Over),
+            "ROT" => Ok(OpKind::Rot),
+            "PICK" => Ok(OpKind::Pick),
+            "DROPN" => Ok(OpKind::Dropn),
+            "LOADP" => Ok(OpKind::Loadp),
+            "PUTC" => Ok(OpKind::Putc),
+            "PUTN" => Ok(OpKind::Putn),
+            "PRINTS" => Ok(OpKind::Prints),
+            "PRINTP" => Ok(OpKind::Printp),
+            "GETC" => Ok(OpKind::Getc),
+            "READN" => Ok(OpKind::Readn),
+            "SYSCALL" => Ok(OpKind::Syscall),
 
-            _ => Err(format!("unknown string op kind: '{}'", value)),
+            _ => Err(VmError::UnknownMnemonic(value)),
         }
     }
 }
 
-impl Into<u8> for OpKind {
-    fn into(self) -> u8 {
-        match self {
+impl From<OpKind> for u8 {
+    fn from(kind: OpKind) -> u8 {
+        match kind {
             OpKind::Push => 0x00,
             OpKind::Pop => 0x01,
             OpKind::Echo => 0x02,
@@ -78,10 +193,95 @@ impl Into<u8> for OpKind {
             OpKind::Goif => 0x08,
             OpKind::Copy => 0x09,
             OpKind::Halt => 0x0a,
+            OpKind::Call => 0x0b,
+            OpKind::Ret => 0x0c,
+            OpKind::Load => 0x0d,
+            OpKind::Store => 0x0e,
+            OpKind::Loadi => 0x0f,
+            OpKind::Storei => 0x10,
+            OpKind::Eq => 0x11,
+            OpKind::Ne => 0x12,
+            OpKind::Lt => 0x13,
+            OpKind::Le => 0x14,
+            OpKind::Gt => 0x15,
+            OpKind::Ge => 0x16,
+            OpKind::And => 0x17,
+            OpKind::Or => 0x18,
+            OpKind::Xor => 0x19,
+            OpKind::Not => 0x1a,
+            OpKind::Shl => 0x1b,
+            OpKind::Shr => 0x1c,
+            OpKind::Mod => 0x1d,
+            OpKind::Goifz => 0x1e,
+            OpKind::Swap => 0x1f,
+            OpKind::Over => 0x20,
+            OpKind::Rot => 0x21,
+            OpKind::Pick => 0x22,
+            OpKind::Dropn => 0x23,
+            OpKind::Loadp => 0x24,
+            OpKind::Putc => 0x25,
+            OpKind::Putn => 0x26,
+            OpKind::Prints => 0x27,
+            OpKind::Printp => 0x28,
+            OpKind::Getc => 0x29,
+            OpKind::Readn => 0x2a,
+            OpKind::Syscall => 0x2b,
         }
     }
 }
 
+impl fmt::Display for OpKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mnemonic = match self {
+            OpKind::Push => "PUSH",
+            OpKind::Pop => "POP",
+            OpKind::Echo => "ECHO",
+            OpKind::Add => "ADD",
+            OpKind::Sub => "SUB",
+            OpKind::Mul => "MUL",
+            OpKind::Div => "DIV",
+            OpKind::Goto => "GOTO",
+            OpKind::Goif => "GOIF",
+            OpKind::Copy => "COPY",
+            OpKind::Halt => "HALT",
+            OpKind::Call => "CALL",
+            OpKind::Ret => "RET",
+            OpKind::Load => "LOAD",
+            OpKind::Store => "STORE",
+            OpKind::Loadi => "LOADI",
+            OpKind::Storei => "STOREI",
+            OpKind::Eq => "EQ",
+            OpKind::Ne => "NE",
+            OpKind::Lt => "LT",
+            OpKind::Le => "LE",
+            OpKind::Gt => "GT",
+            OpKind::Ge => "GE",
+            OpKind::And => "AND",
+            OpKind::Or => "OR",
+            OpKind::Xor => "XOR",
+            OpKind::Not => "NOT",
+            OpKind::Shl => "SHL",
+            OpKind::Shr => "SHR",
+            OpKind::Mod => "MOD",
+            OpKind::Goifz => "GOIFZ",
+            OpKind::Swap => "SWAP",
+            OpKind::Over => "OVER",
+            OpKind::Rot => "ROT",
+            OpKind::Pick => "PICK",
+            OpKind::Dropn => "DROPN",
+            OpKind::Loadp => "LOADP",
+            OpKind::Putc => "PUTC",
+            OpKind::Putn => "PUTN",
+            OpKind::Prints => "PRINTS",
+            OpKind::Printp => "PRINTP",
+            OpKind::Getc => "GETC",
+            OpKind::Readn => "READN",
+            OpKind::Syscall => "SYSCALL",
+        };
+        write!(f, "{}", mnemonic)
+    }
+}
+
 impl OpKind {
     pub fn has_operand(&self) -> bool {
         match self {
@@ -96,17 +296,61 @@ impl OpKind {
             OpKind::Goif => true,
             OpKind::Copy => false,
             OpKind::Halt => false,
+            OpKind::Call => true,
+            OpKind::Ret => false,
+            OpKind::Load => true,
+            OpKind::Store => true,
+            OpKind::Loadi => false,
+            OpKind::Storei => false,
+            OpKind::Eq => false,
+            OpKind::Ne => false,
+            OpKind::Lt => false,
+            OpKind::Le => false,
+            OpKind::Gt => false,
+            OpKind::Ge => false,
+            OpKind::And => false,
+            OpKind::Or => false,
+            OpKind::Xor => false,
+            OpKind::Not => false,
+            OpKind::Shl => false,
+            OpKind::Shr => false,
+            OpKind::Mod => false,
+            OpKind::Goifz => true,
+            OpKind::Swap => false,
+            OpKind::Over => false,
+            OpKind::Rot => false,
+            OpKind::Pick => true,
+            OpKind::Dropn => true,
+            OpKind::Loadp => false,
+            OpKind::Putc => false,
+            OpKind::Putn => false,
+            OpKind::Prints => false,
+            OpKind::Printp => false,
+            OpKind::Getc => false,
+            OpKind::Readn => false,
+            OpKind::Syscall => true,
         }
     }
+
+    pub fn has_label(&self) -> bool {
+        matches!(
+            self,
+            OpKind::Goto | OpKind::Goif | OpKind::Goifz | OpKind::Call
+        )
+    }
+
+    pub fn has_cell(&self) -> bool {
+        matches!(self, OpKind::Load | OpKind::Store)
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Op(pub OpKind, pub Option<Word>);
 
-impl Into<Vec<u8>> for Op {
-    fn into(self) -> Vec<u8> {
-        let mut vec: Vec<u8> = vec![self.0.into()];
-        if let Some(word) = self.1 {
+impl From<Op> for Vec<u8> {
+    fn from(op: Op) -> Vec<u8> {
+        let mut vec: Vec<u8> = vec![op.0.into()];
+        if let Some(word) = op.1 {
             vec.append(&mut word.to_be_bytes().to_vec());
         }
         vec
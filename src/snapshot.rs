@@ -0,0 +1,365 @@
+use crate::error::VmError;
+use crate::object::Reader;
+use crate::op::Word;
+
+pub const MAGIC: [u8; 4] = *b"VMSS";
+pub const VERSION: u16 = 1;
+
+/// The complete execution state of a `Machine`, without its I/O, syscalls or interrupts.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    pub program: Vec<u8>,
+    pub ip: usize,
+    pub halted: bool,
+    /// Bottom first.
+    pub stack: Vec<Word>,
+    pub returns: Vec<usize>,
+    pub memory: Vec<Word>,
+}
+
+/*
+ * Layout (all integers are big endian):
+ *
+ *   magic    [u8; 4]     "VMSS"
+ *   version  u16
+ *   ip       u32
+ *   halted   u8          0 or 1
+ *   program  u32 length, then the bytecode
+ *   stack    u32 count, then the words, bottom first
+ *   returns  u32 count, then the u32 return addresses
+ *   memory   u32 count, then the words
+ */
+impl From<&Snapshot> for Vec<u8> {
+    fn from(snapshot: &Snapshot) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_be_bytes());
+        bytes.extend((snapshot.ip as u32).to_be_bytes());
+        bytes.push(snapshot.halted.into());
+
+        bytes.extend((snapshot.program.len() as u32).to_be_bytes());
+        bytes.extend(&snapshot.program);
+
+        bytes.extend((snapshot.stack.len() as u32).to_be_bytes());
+        for word in &snapshot.stack {
+            bytes.extend(word.to_be_bytes());
+        }
+
+        bytes.extend((snapshot.returns.len() as u32).to_be_bytes());
+        for address in &snapshot.returns {
+            bytes.extend((*address as u32).to_be_bytes());
+        }
+
+        bytes.extend((snapshot.memory.len() as u32).to_be_bytes());
+        for word in &snapshot.memory {
+            bytes.extend(word.to_be_bytes());
+        }
+
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for Snapshot {
+    type Error = VmError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        let mut reader = Reader::new(bytes);
+        let malformed = |_| VmError::MalformedSnapshot;
+
+        if reader.take(MAGIC.len()).ok() != Some(&MAGIC[..])
+            || reader.u16().map_err(malformed)? != VERSION
+        {
+            return Err(VmError::MalformedSnapshot);
+        }
+
+        let ip = reader.u32().map_err(malformed)? as usize;
+        let halted = match reader.take(1).map_err(malformed)?[0] {
+            0 => false,
+            1 => true,
+            _ => return Err(VmError::MalformedSnapshot),
+        };
+
+        let length = reader.u32().map_err(malformed)? as usize;
+        let program = reader.take(length).map_err(malformed)?.to_vec();
+
+        let mut stack = Vec::new();
+        for _ in 0..reader.u32().map_err(malformed)? {
+            stack.push(reader.u16().map_err(malformed)? as Word);
+        }
+
+        let mut returns = Vec::new();
+        for _ in 0..reader.u32().map_err(malformed)? {
+            returns.push(reader.u32().map_err(malformed)? as usize);
+        }
+
+        let mut memory = Vec::new();
+        for _ in 0..reader.u32().map_err(malformed)? {
+            memory.push(reader.u16().map_err(malformed)? as Word);
+        }
+
+        if !reader.is_empty() {
+            return Err(VmError::MalformedSnapshot);
+        }
+
+        Ok(Snapshot {
+            program,
+            ip,
+            halted,
+            stack,
+            returns,
+            memory,
+        })
+    }
+}
+
+impl Snapshot {
+    pub fn to_json(&self) -> String {
+        let array = |values: Vec<String>| format!("[{}]", values.join(", "));
+        let words = |words: &[Word]| array(words.iter().map(Word::to_string).collect());
+
+        format!(
+            "{{\n  \"version\": {},\n  \"ip\": {},\n  \"halted\": {},\n  \"program\": {},\n  \"stack\": {},\n  \"returns\": {},\n  \"memory\": {}\n}}\n",
+            VERSION,
+            self.ip,
+            self.halted,
+            array(self.program.iter().map(u8::to_string).collect()),
+            words(&self.stack),
+            array(self.returns.iter().map(usize::to_string).collect()),
+            words(&self.memory),
+        )
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, VmError> {
+        let mut parser = Parser {
+            bytes: json.as_bytes(),
+            offset: 0,
+        };
+        let value = parser.value(0)?;
+        parser.skip_whitespace();
+        if parser.offset != parser.bytes.len() {
+            return Err(VmError::MalformedSnapshot);
+        }
+
+        let Json::Object(fields) = value else {
+            return Err(VmError::MalformedSnapshot);
+        };
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value)
+                .ok_or(VmError::MalformedSnapshot)
+        };
+
+        if field("version")?.number::<u16>()? != VERSION {
+            return Err(VmError::MalformedSnapshot);
+        }
+        let Json::Bool(halted) = field("halted")? else {
+            return Err(VmError::MalformedSnapshot);
+        };
+
+        Ok(Snapshot {
+            program: field("program")?.numbers()?,
+            ip: field("ip")?.number()?,
+            halted: *halted,
+            stack: field("stack")?.numbers()?,
+            returns: field("returns")?.numbers()?,
+            memory: field("memory")?.numbers()?,
+        })
+    }
+}
+
+/* Just enough JSON to read back what `to_json` writes */
+enum Json {
+    Bool(bool),
+    Number(i64),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn number<T: TryFrom<i64>>(&self) -> Result<T, VmError> {
+        match self {
+            Json::Number(value) => T::try_from(*value).map_err(|_| VmError::MalformedSnapshot),
+            _ => Err(VmError::MalformedSnapshot),
+        }
+    }
+
+    fn numbers<T: TryFrom<i64>>(&self) -> Result<Vec<T>, VmError> {
+        match self {
+            Json::Array(values) => values.iter().map(Json::number).collect(),
+            _ => Err(VmError::MalformedSnapshot),
+        }
+    }
+}
+
+const MAX_DEPTH: usize = 2;
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self
+            .bytes
+            .get(self.offset)
+            .is_some_and(|byte| byte.is_ascii_whitespace())
+        {
+            self.offset += 1;
+        }
+    }
+
+    /// Consumes `token` after any whitespace, returning whether it was there.
+    fn next_is(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        let found = self.bytes[self.offset..].starts_with(token.as_bytes());
+        if found {
+            self.offset += token.len();
+        }
+        found
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), VmError> {
+        match self.next_is(token) {
+            true => Ok(()),
+            false => Err(VmError::MalformedSnapshot),
+        }
+    }
+
+    /// Containers nest at most `MAX_DEPTH` deep, an object of arrays.
+    fn value(&mut self, depth: usize) -> Result<Json, VmError> {
+        if self.next_is("true") {
+            return Ok(Json::Bool(true));
+        }
+        if self.next_is("false") {
+            return Ok(Json::Bool(false));
+        }
+        if depth == MAX_DEPTH && (self.next_is("[") || self.next_is("{")) {
+            return Err(VmError::MalformedSnapshot);
+        }
+        if self.next_is("[") {
+            let mut values = Vec::new();
+            if !self.next_is("]") {
+                loop {
+                    values.push(self.value(depth + 1)?);
+                    if self.next_is("]") {
+                        break;
+                    }
+                    self.expect(",")?;
+                }
+            }
+            return Ok(Json::Array(values));
+        }
+        if self.next_is("{") {
+            let mut fields = Vec::new();
+            if !self.next_is("}") {
+                loop {
+                    let key = self.string()?;
+                    self.expect(":")?;
+                    fields.push((key, self.value(depth + 1)?));
+                    if self.next_is("}") {
+                        break;
+                    }
+                    self.expect(",")?;
+                }
+            }
+            return Ok(Json::Object(fields));
+        }
+        self.number()
+    }
+
+    fn number(&mut self) -> Result<Json, VmError> {
+        let start = self.offset;
+        if self.bytes.get(self.offset) == Some(&b'-') {
+            self.offset += 1;
+        }
+        while self
+            .bytes
+            .get(self.offset)
+            .is_some_and(|byte| byte.is_ascii_digit())
+        {
+            self.offset += 1;
+        }
+
+        std::str::from_utf8(&self.bytes[start..self.offset])
+            .ok()
+            .and_then(|text| text.parse().ok())
+            .map(Json::Number)
+            .ok_or(VmError::MalformedSnapshot)
+    }
+
+    /// A string without escapes, which is all the keys need.
+    fn string(&mut self) -> Result<String, VmError> {
+        self.expect("\"")?;
+        let length = self.bytes[self.offset..]
+            .iter()
+            .position(|&byte| byte == b'"')
+            .ok_or(VmError::MalformedSnapshot)?;
+        let string = &self.bytes[self.offset..self.offset + length];
+        self.offset += length + 1;
+
+        match string.contains(&b'\\') {
+            true => Err(VmError::MalformedSnapshot),
+            false => String::from_utf8(string.to_vec()).map_err(|_| VmError::MalformedSnapshot),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            program: vec![0x00, 0x00, 0x01, 0x0a],
+            ip: 3,
+            halted: false,
+            stack: vec![1, -2],
+            returns: vec![3],
+            memory: vec![0, -32768, 32767],
+        }
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let bytes: Vec<u8> = (&snapshot()).into();
+        assert_eq!(Snapshot::try_from(&bytes[..]), Ok(snapshot()));
+
+        assert_eq!(
+            Snapshot::try_from(&bytes[..bytes.len() - 1]),
+            Err(VmError::MalformedSnapshot)
+        );
+        assert_eq!(
+            Snapshot::try_from(&b"VMRS"[..]),
+            Err(VmError::MalformedSnapshot)
+        );
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let json = snapshot().to_json();
+        assert_eq!(
+            json,
+            "{\n  \"version\": 1,\n  \"ip\": 3,\n  \"halted\": false,\n  \"program\": [0, 0, 1, 10],\n  \"stack\": [1, -2],\n  \"returns\": [3],\n  \"memory\": [0, -32768, 32767]\n}\n"
+        );
+        assert_eq!(Snapshot::from_json(&json), Ok(snapshot()));
+
+        assert_eq!(
+            Snapshot::from_json(&json.replace("32767", "32768")),
+            Err(VmError::MalformedSnapshot)
+        );
+        assert_eq!(
+            Snapshot::from_json(&json.replace("\"ip\": 3,", "")),
+            Err(VmError::MalformedSnapshot)
+        );
+        assert_eq!(
+            Snapshot::from_json(&json.replace("[1, -2]", "[[1], -2]")),
+            Err(VmError::MalformedSnapshot)
+        );
+        assert_eq!(
+            Snapshot::from_json(&"[".repeat(1 << 20)),
+            Err(VmError::MalformedSnapshot)
+        );
+    }
+}
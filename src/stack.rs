@@ -1,5 +1,6 @@
 use std::fmt;
 
+use crate::error::VmError;
 use crate::op::Word;
 
 const STACK_CAPACITY: usize = 1 << 10;
@@ -17,16 +18,44 @@ impl Stack {
         }
     }
 
-    pub fn head(&mut self) -> Result<Word, String> {
+    pub fn len(&self) -> usize {
+        self.index
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index == 0
+    }
+
+    /// The words on the stack, bottom first.
+    pub fn as_slice(&self) -> &[Word] {
+        &self.buffer[..self.index]
+    }
+
+    pub fn head(&mut self) -> Result<Word, VmError> {
         if self.index == 0 {
-            return Err("stack underflow".to_string());
+            return Err(VmError::StackUnderflow);
         }
         Ok(self.buffer[self.index - 1])
     }
 
-    pub fn push(&mut self, word: Word) -> Result<(), String> {
+    pub fn peek_at(&self, depth: usize) -> Result<Word, VmError> {
+        if depth >= self.index {
+            return Err(VmError::StackUnderflow);
+        }
+        Ok(self.buffer[self.index - 1 - depth])
+    }
+
+    pub fn set_at(&mut self, depth: usize, word: Word) -> Result<(), VmError> {
+        if depth >= self.index {
+            return Err(VmError::StackUnderflow);
+        }
+        self.buffer[self.index - 1 - depth] = word;
+        Ok(())
+    }
+
+    pub fn push(&mut self, word: Word) -> Result<(), VmError> {
         if self.index >= STACK_CAPACITY {
-            return Err("stack overflow".to_string());
+            return Err(VmError::StackOverflow);
         }
 
         self.buffer[self.index] = word;
@@ -34,13 +63,43 @@ impl Stack {
         Ok(())
     }
 
-    pub fn pop(&mut self) -> Result<Word, String> {
+    pub fn pop(&mut self) -> Result<Word, VmError> {
         if self.index == 0 {
-            return Err("stack underflow".to_string());
+            return Err(VmError::StackUnderflow);
         }
         self.index -= 1;
         Ok(self.buffer[self.index])
     }
+
+    pub fn swap(&mut self) -> Result<(), VmError> {
+        if self.index < 2 {
+            return Err(VmError::StackUnderflow);
+        }
+        self.buffer.swap(self.index - 1, self.index - 2);
+        Ok(())
+    }
+
+    pub fn rot(&mut self) -> Result<(), VmError> {
+        if self.index < 3 {
+            return Err(VmError::StackUnderflow);
+        }
+        self.buffer[self.index - 3..self.index].rotate_left(1);
+        Ok(())
+    }
+
+    pub fn drop_n(&mut self, count: usize) -> Result<(), VmError> {
+        if count > self.index {
+            return Err(VmError::StackUnderflow);
+        }
+        self.index -= count;
+        Ok(())
+    }
+}
+
+impl Default for Stack {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl fmt::Display for Stack {
@@ -91,7 +150,61 @@ mod tests {
     #[test]
     fn test_stack_underflow() {
         let mut stack = Stack::new();
-        assert!(stack.pop().is_err());
+        assert_eq!(stack.pop(), Err(VmError::StackUnderflow));
+        assert_eq!(stack.head(), Err(VmError::StackUnderflow));
+    }
+
+    #[test]
+    fn test_peek_at() {
+        let mut stack = Stack::new();
+        stack.push(10).unwrap();
+        stack.push(20).unwrap();
+        assert_eq!(stack.peek_at(0).unwrap(), 20);
+        assert_eq!(stack.peek_at(1).unwrap(), 10);
+        assert_eq!(stack.peek_at(2), Err(VmError::StackUnderflow));
+    }
+
+    #[test]
+    fn test_set_at() {
+        let mut stack = Stack::new();
+        stack.push(10).unwrap();
+        stack.push(20).unwrap();
+        stack.set_at(1, 15).unwrap();
+        assert_eq!(format!("{}", stack), "20 -> 15 -> None");
+        assert_eq!(stack.set_at(2, 0), Err(VmError::StackUnderflow));
+    }
+
+    #[test]
+    fn test_swap() {
+        let mut stack = Stack::new();
+        stack.push(10).unwrap();
+        assert_eq!(stack.swap(), Err(VmError::StackUnderflow));
+        stack.push(20).unwrap();
+        stack.swap().unwrap();
+        assert_eq!(format!("{}", stack), "10 -> 20 -> None");
+    }
+
+    #[test]
+    fn test_rot() {
+        let mut stack = Stack::new();
+        stack.push(10).unwrap();
+        stack.push(20).unwrap();
+        assert_eq!(stack.rot(), Err(VmError::StackUnderflow));
+        stack.push(30).unwrap();
+        stack.rot().unwrap();
+        assert_eq!(format!("{}", stack), "10 -> 30 -> 20 -> None");
+    }
+
+    #[test]
+    fn test_drop_n() {
+        let mut stack = Stack::new();
+        stack.push(10).unwrap();
+        stack.push(20).unwrap();
+        stack.push(30).unwrap();
+        stack.drop_n(2).unwrap();
+        assert_eq!(format!("{}", stack), "10 -> None");
+        assert_eq!(stack.drop_n(2), Err(VmError::StackUnderflow));
+        assert_eq!(stack.len(), 1);
     }
 
     #[test]
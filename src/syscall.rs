@@ -0,0 +1,50 @@
+use crate::error::VmError;
+use crate::op::Word;
+use crate::stack::Stack;
+
+/// A host function bytecode can invoke with `SYSCALL n`, an error aborts execution.
+pub type Syscall = Box<dyn FnMut(&mut SyscallContext) -> Result<(), VmError> + Send>;
+
+/// The part of the machine a syscall may touch.
+pub struct SyscallContext<'a> {
+    stack: &'a mut Stack,
+    memory: &'a mut [Word],
+}
+
+impl<'a> SyscallContext<'a> {
+    pub(crate) fn new(stack: &'a mut Stack, memory: &'a mut [Word]) -> Self {
+        Self { stack, memory }
+    }
+
+    pub fn stack(&self) -> &Stack {
+        self.stack
+    }
+
+    pub fn memory(&self) -> &[Word] {
+        self.memory
+    }
+
+    pub fn push(&mut self, value: Word) -> Result<(), VmError> {
+        self.stack.push(value)
+    }
+
+    pub fn pop(&mut self) -> Result<Word, VmError> {
+        self.stack.pop()
+    }
+
+    pub fn load(&self, cell: Word) -> Result<Word, VmError> {
+        Ok(self.memory[self.cell(cell)?])
+    }
+
+    pub fn store(&mut self, cell: Word, value: Word) -> Result<(), VmError> {
+        self.memory[self.cell(cell)?] = value;
+        Ok(())
+    }
+
+    fn cell(&self, value: Word) -> Result<usize, VmError> {
+        usize::try_from(value)
+            .ok()
+            .filter(|&cell| cell < self.memory.len())
+            .ok_or(VmError::MemoryOutOfBounds(value))
+    }
+}
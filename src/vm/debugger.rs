@@ -0,0 +1,340 @@
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+
+use vmrs::disassembler::{self, Labels};
+use vmrs::{Machine, Object, OpKind, VmError, Word};
+
+const CONTEXT: usize = 4;
+
+const HELP: &str = "\
+commands:
+    step                  execute a single instruction
+    next                  execute a single instruction, stepping over calls
+    continue              run until a breakpoint or halt
+    break <addr|label>    set a breakpoint
+    print stack           print the stack
+    print ip              print the instruction pointer
+    set stack[i] = v      overwrite the i-th stack entry from the top
+    disassemble           disassemble around the instruction pointer
+    quit                  exit the debugger";
+
+pub struct Debugger {
+    machine: Machine,
+    object: Object,
+    labels: Labels,
+    breakpoints: BTreeSet<usize>,
+    fault: Option<VmError>,
+}
+
+impl Debugger {
+    pub fn new(machine: Machine, object: Object) -> Self {
+        let data = &object.data_ranges;
+        let labels = disassembler::decode(&object.code, data)
+            .and_then(|ops| {
+                disassembler::labels(&ops, data, object.code.len(), object.symbols.as_ref())
+            })
+            .unwrap_or_default();
+
+        Self {
+            machine,
+            object,
+            labels,
+            breakpoints: BTreeSet::new(),
+            fault: None,
+        }
+    }
+
+    pub fn run(&mut self) {
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+
+        loop {
+            print!("(vm) ");
+            io::stdout().flush().expect("could not flush stdout");
+
+            let line = match lines.next() {
+                Some(Ok(line)) => line,
+                _ => break,
+            };
+
+            match line.trim() {
+                "" => {}
+                "quit" | "q" => break,
+                command => {
+                    if let Err(message) = self.command(command) {
+                        println!("error: {}", message);
+                    }
+                }
+            }
+        }
+    }
+
+    fn command(&mut self, command: &str) -> Result<(), String> {
+        let (name, rest) = command.split_once(' ').unwrap_or((command, ""));
+        let rest = rest.trim();
+
+        match name {
+            "help" | "h" => println!("{}", HELP),
+            "step" | "s" => {
+                self.step()?;
+                self.location();
+            }
+            "next" | "n" => {
+                self.next()?;
+                self.location();
+            }
+            "continue" | "c" => {
+                self.resume()?;
+                self.location();
+            }
+            "break" | "b" => {
+                let address = self.resolve(rest)?;
+                self.breakpoints.insert(address);
+                println!("breakpoint at {:0>4}", address);
+            }
+            "print" | "p" => match rest {
+                "stack" => println!("{}", self.machine.stack()),
+                "ip" => println!("{:0>4}", self.machine.ip()),
+                _ => return Err(format!("cannot print '{}'", rest)),
+            },
+            "set" => self.set(rest)?,
+            "disassemble" | "disas" => match rest {
+                "" | "around ip" => self.disassemble(),
+                _ => return Err(format!("cannot disassemble '{}'", rest)),
+            },
+            _ => return Err(format!("unknown command '{}', try 'help'", name)),
+        }
+
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<(), String> {
+        if let Some(error) = &self.fault {
+            return Err(format!("program faulted: {}", error));
+        }
+        if self.machine.halted() {
+            return Err("program halted".to_string());
+        }
+
+        if let Err(error) = self.machine.step() {
+            self.fault = Some(error.clone());
+            return Err(error.to_string());
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<(), String> {
+        let depth = self.machine.call_depth();
+        let is_call = disassembler::decode_at(self.machine.program(), self.machine.ip())
+            .is_ok_and(|op| op.0 == OpKind::Call);
+
+        self.step()?;
+        if is_call {
+            while self.machine.call_depth() > depth && !self.stopped() {
+                self.step()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<(), String> {
+        self.step()?;
+        while !self.stopped() {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    fn stopped(&self) -> bool {
+        self.machine.halted() || self.breakpoints.contains(&self.machine.ip())
+    }
+
+    fn resolve(&self, target: &str) -> Result<usize, String> {
+        if let Ok(address) = target.parse::<usize>() {
+            return Ok(address);
+        }
+        self.labels
+            .iter()
+            .find(|(_, names)| names.iter().any(|name| name == target))
+            .map(|(&address, _)| address)
+            .ok_or(format!("unknown address or label '{}'", target))
+    }
+
+    fn set(&mut self, assignment: &str) -> Result<(), String> {
+        let usage = || "usage: set stack[i] = v".to_string();
+
+        let (target, value) = assignment.split_once('=').ok_or_else(usage)?;
+        let depth = target
+            .trim()
+            .strip_prefix("stack[")
+            .and_then(|index| index.strip_suffix(']'))
+            .and_then(|index| index.trim().parse::<usize>().ok())
+            .ok_or_else(usage)?;
+        let value = value.trim().parse::<Word>().map_err(|_| usage())?;
+
+        self.machine
+            .stack_mut()
+            .set_at(depth, value)
+            .map_err(|error| error.to_string())?;
+        println!("{}", self.machine.stack());
+        Ok(())
+    }
+
+    fn location(&self) {
+        if self.machine.halted() {
+            println!("program halted");
+            return;
+        }
+        if self.breakpoints.contains(&self.machine.ip()) {
+            println!("breakpoint hit");
+        }
+        self.print_op(self.machine.ip());
+    }
+
+    fn print_op(&self, offset: usize) {
+        let marker = match offset == self.machine.ip() {
+            true => "=>",
+            false => "  ",
+        };
+        let label = self
+            .labels
+            .get(&offset)
+            .and_then(|names| names.first())
+            .map_or("", String::as_str);
+        let line = self.object.line_at(offset).map_or(String::new(), |line| {
+            format!(" | line {}, col {}", line.row, line.col)
+        });
+
+        match disassembler::decode_at(self.machine.program(), offset) {
+            Ok(op) => println!(
+                "{} {:0>4} {: <8} {: <20}{}",
+                marker,
+                offset,
+                label,
+                disassembler::format_op(&op, &self.labels),
+                line
+            ),
+            Err(error) => println!("{} {:0>4} {: <8} <{}>", marker, offset, label, error),
+        }
+    }
+
+    fn disassemble(&self) {
+        let ip = self.machine.ip();
+        let offsets: Vec<usize> =
+            match disassembler::decode(self.machine.program(), &self.object.data_ranges) {
+                Ok(ops) => ops.into_iter().map(|(offset, _)| offset).collect(),
+                Err(_) => vec![ip],
+            };
+
+        let position = offsets.iter().position(|&offset| offset >= ip).unwrap_or(0);
+        let start = position.saturating_sub(CONTEXT);
+        let end = (position + CONTEXT + 1).min(offsets.len());
+
+        for &offset in &offsets[start..end] {
+            self.print_op(offset);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use vmrs::object::{DataKind, DataRange};
+
+    /*
+     * 0000  push 1
+     * 0003  call f
+     * 0006  halt
+     * 0007  @f push 2
+     * 0010  ret
+     */
+    fn debugger() -> Debugger {
+        let mut object = Object::new(vec![
+            OpKind::Push.into(),
+            0x00,
+            0x01,
+            OpKind::Call.into(),
+            0x00,
+            0x07,
+            OpKind::Halt.into(),
+            OpKind::Push.into(),
+            0x00,
+            0x02,
+            OpKind::Ret.into(),
+        ]);
+        object.symbols = Some(BTreeMap::from([("f".to_string(), 7)]));
+        let machine = Machine::try_from_object(&object).unwrap();
+        Debugger::new(machine, object)
+    }
+
+    #[test]
+    fn test_break() {
+        let mut debugger = debugger();
+        debugger.command("break f").unwrap();
+        debugger.command("b 3").unwrap();
+        assert_eq!(debugger.breakpoints, BTreeSet::from([3, 7]));
+        assert!(debugger.command("break nowhere").is_err());
+
+        debugger.command("continue").unwrap();
+        assert_eq!(debugger.machine.ip(), 3);
+        debugger.command("continue").unwrap();
+        assert_eq!(debugger.machine.ip(), 7);
+        assert_eq!(debugger.machine.call_depth(), 1);
+    }
+
+    #[test]
+    fn test_next_steps_over_calls() {
+        let mut debugger = debugger();
+        debugger.command("next").unwrap();
+        assert_eq!(debugger.machine.ip(), 3);
+        debugger.command("next").unwrap();
+        assert_eq!(debugger.machine.ip(), 6);
+        assert_eq!(debugger.machine.call_depth(), 0);
+        assert_eq!(debugger.machine.stack().as_slice(), &[1, 2]);
+
+        debugger.command("next").unwrap();
+        assert!(debugger.machine.halted());
+        assert_eq!(debugger.command("step"), Err("program halted".to_string()));
+    }
+
+    #[test]
+    fn test_data_is_skipped() {
+        let mut object = Object::new(vec![
+            OpKind::Goto.into(),
+            0x00,
+            0x05,
+            b'h',
+            0x00,
+            OpKind::Halt.into(),
+        ]);
+        object.symbols = Some(BTreeMap::from([("end".to_string(), 5)]));
+        object.data_ranges = vec![DataRange {
+            offset: 3,
+            length: 2,
+            kind: DataKind::Byte,
+        }];
+        let machine = Machine::try_from_object(&object).unwrap();
+        let mut debugger = Debugger::new(machine, object);
+
+        debugger.command("break end").unwrap();
+        debugger.command("continue").unwrap();
+        assert_eq!(debugger.machine.ip(), 5);
+        debugger.command("disassemble").unwrap();
+    }
+
+    #[test]
+    fn test_set_stack() {
+        let mut debugger = debugger();
+        debugger.command("step").unwrap();
+        debugger.command("set stack[0] = -5").unwrap();
+        assert_eq!(debugger.machine.stack().as_slice(), &[-5]);
+
+        assert!(debugger.command("set stack[1] = 0").is_err());
+        assert_eq!(
+            debugger.command("set ip = 0"),
+            Err("usage: set stack[i] = v".to_string())
+        );
+        assert!(debugger.command("frobnicate").is_err());
+    }
+}
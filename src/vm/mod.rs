@@ -1,19 +1,45 @@
+pub mod debugger;
+
+use debugger::Debugger;
+
 use std::env;
 use std::fs;
 use std::process::exit;
-use vmrs::Machine;
-
-const DEBUG: bool = false;
+use vmrs::{Machine, Object, RunOutcome, VmError};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-
-    if args.len() < 2 {
-        eprintln!("Usage: {} <path>", args[0]);
+    let usage = || -> ! {
+        eprintln!("Usage: {} [--debug] [--max-steps <n>] <path>", args[0]);
         exit(1);
+    };
+
+    let mut debug = false;
+    let mut max_steps = None;
+    let mut paths = Vec::new();
+    let mut rest = args.iter().skip(1);
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--debug" => debug = true,
+            "--max-steps" => match rest.next().and_then(|steps| steps.parse::<usize>().ok()) {
+                Some(steps) => max_steps = Some(steps),
+                None => usage(),
+            },
+            _ => paths.push(arg),
+        }
+    }
+
+    if paths.len() != 1 {
+        usage();
     }
 
-    let path = &args[1];
+    /* The debugger steps on its own and would ignore the budget */
+    if debug && max_steps.is_some() {
+        eprintln!("ERROR: --max-steps cannot be combined with --debug");
+        usage();
+    }
+
+    let path = paths[0];
     let result = fs::read(path);
 
     if result.is_err() {
@@ -21,10 +47,43 @@ fn main() {
         exit(1);
     }
 
-    let mut machine = Machine::try_new(&result.unwrap()).expect("oops");
+    let bytes = result.unwrap();
+    let load = |bytes: &[u8]| -> Result<(Object, Machine), VmError> {
+        let object = Object::try_from(bytes)?;
+        let machine = Machine::try_from_object(&object)?;
+        Ok((object, machine))
+    };
 
-    if let Err(error) = machine.run(DEBUG) {
-        eprintln!("ERROR: {}", error);
-        exit(1);
+    let (object, mut machine) = match load(&bytes) {
+        Err(error) => {
+            eprintln!("ERROR: {}", error);
+            exit(1);
+        }
+        Ok(loaded) => loaded,
+    };
+
+    if debug {
+        Debugger::new(machine, object).run();
+        return;
+    }
+
+    let Some(max_steps) = max_steps else {
+        if let Err(error) = machine.run(false) {
+            eprintln!("ERROR: {}", error);
+            exit(1);
+        }
+        return;
+    };
+
+    match machine.run_for(max_steps) {
+        RunOutcome::Halted => {}
+        RunOutcome::OutOfFuel => {
+            eprintln!("ERROR: step limit of {} reached", max_steps);
+            exit(1);
+        }
+        RunOutcome::Trapped(error) => {
+            eprintln!("ERROR: {}", error);
+            exit(1);
+        }
     }
 }